@@ -5,29 +5,117 @@ use zip::ZipArchive as UpstreamZipArchive;
 use tar::Archive as UpstreamTarArchive;
 use tar::EntryType;
 
-use std::io::Cursor;
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::BufReader;
 use std::io::Read;
+use std::io::{Seek, SeekFrom};
 use std::iter::Iterator;
-use std::path::PathBuf;
+use std::path::{Component, Path, PathBuf};
 
+use bzip2::read::BzDecoder;
+use flate2::read::GzDecoder;
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use sevenz_rust::{Password, SevenZReader};
 use xz2::read::XzDecoder;
+use zstd::stream::read::Decoder as ZstdDecoder;
 
-pub trait Archive<'a> {
-    /// func: iterator value, max size, file name, file contents
+/// File type bits of a Unix mode.
+const S_IFMT: u32 = 0o170000;
+/// File type bits of a symlink.
+const S_IFLNK: u32 = 0o120000;
+
+/// What an entry of an archive should be extracted as.
+pub enum EntryKind {
+    File,
+    /// A symbolic link, pointing at the given target.
+    Symlink(PathBuf),
+    /// A hard link to another entry of the archive, given by its path in the archive.
+    HardLink(PathBuf),
+}
+
+/// An individual entry of an archive.
+pub struct ArchiveEntry {
+    pub path: PathBuf,
+    pub kind: EntryKind,
+    /// Unix permission bits, if the archive records them.
+    pub mode: Option<u32>,
+}
+
+impl ArchiveEntry {
+    /// Describes an entry from its Unix mode, as recorded by .zip and .7z files. These
+    /// store the target of symlinks as the contents of the entry.
+    fn from_unix_mode(
+        path: PathBuf,
+        mode: Option<u32>,
+        data: &mut dyn Read,
+    ) -> Result<ArchiveEntry, String> {
+        let kind = match mode {
+            Some(mode) if mode & S_IFMT == S_IFLNK => {
+                let mut target = String::new();
+                data.read_to_string(&mut target)
+                    .map_err(|x| format!("Unable to read link {:?}: {:?}", path, x))?;
+
+                EntryKind::Symlink(PathBuf::from(target))
+            }
+            _ => EntryKind::File,
+        };
+
+        Ok(ArchiveEntry {
+            path,
+            kind,
+            mode: mode.map(|x| x & 0o777),
+        })
+    }
+}
+
+/// Lexically resolves the `.` and `..` components of a relative path. Returns `None` if
+/// the path is absolute, or would escape the directory that it is relative to.
+pub fn normalize_path(path: &Path) -> Option<PathBuf> {
+    let mut result = PathBuf::new();
+
+    for component in path.components() {
+        match component {
+            Component::Normal(v) => result.push(v),
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !result.pop() {
+                    return None;
+                }
+            }
+            Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+
+    Some(result)
+}
+
+pub trait Archive {
+    /// func: iterator value, max size, entry, file contents
     fn for_each(
         &mut self,
-        func: &mut dyn FnMut(usize, Option<usize>, PathBuf, &mut dyn Read) -> Result<(), String>,
+        func: &mut dyn FnMut(
+            usize,
+            Option<usize>,
+            ArchiveEntry,
+            &mut dyn Read,
+        ) -> Result<(), String>,
     ) -> Result<(), String>;
 }
 
-struct ZipArchive<'a> {
-    archive: UpstreamZipArchive<Cursor<&'a [u8]>>,
+struct ZipArchive {
+    archive: UpstreamZipArchive<BufReader<File>>,
 }
 
-impl<'a> Archive<'a> for ZipArchive<'a> {
+impl Archive for ZipArchive {
     fn for_each(
         &mut self,
-        func: &mut dyn FnMut(usize, Option<usize>, PathBuf, &mut dyn Read) -> Result<(), String>,
+        func: &mut dyn FnMut(
+            usize,
+            Option<usize>,
+            ArchiveEntry,
+            &mut dyn Read,
+        ) -> Result<(), String>,
     ) -> Result<(), String> {
         let max = self.archive.len();
 
@@ -41,21 +129,30 @@ impl<'a> Archive<'a> for ZipArchive<'a> {
                 continue;
             }
 
-            func(i, Some(max), archive.mangled_name(), &mut archive)?;
+            // Names are checked as they are, rather than silently sanitised
+            let path = PathBuf::from(archive.name().replace('\\', "/"));
+            let entry = ArchiveEntry::from_unix_mode(path, archive.unix_mode(), &mut archive)?;
+
+            func(i, Some(max), entry, &mut archive)?;
         }
 
         Ok(())
     }
 }
 
-struct TarArchive<'a> {
-    archive: UpstreamTarArchive<Box<dyn Read + 'a>>,
+struct TarArchive {
+    archive: UpstreamTarArchive<Box<dyn Read>>,
 }
 
-impl<'a> Archive<'a> for TarArchive<'a> {
+impl Archive for TarArchive {
     fn for_each(
         &mut self,
-        func: &mut dyn FnMut(usize, Option<usize>, PathBuf, &mut dyn Read) -> Result<(), String>,
+        func: &mut dyn FnMut(
+            usize,
+            Option<usize>,
+            ArchiveEntry,
+            &mut dyn Read,
+        ) -> Result<(), String>,
     ) -> Result<(), String> {
         let entries = self
             .archive
@@ -66,44 +163,764 @@ impl<'a> Archive<'a> for TarArchive<'a> {
             let mut entry =
                 entry.map_err(|v| format!("Failed to read entry from .tar file: {:?}", v))?;
 
-            if entry.header().entry_type() != EntryType::Regular {
-                continue;
-            }
-
             let path = entry
                 .path()
                 .map(PathBuf::from)
                 .map_err(|v| format!("Failed to read entry from .tar file: {:?}", v))?;
 
-            func(i, None, path, &mut entry)?;
+            let link_name = || {
+                entry
+                    .link_name()
+                    .map_err(|v| format!("Failed to read entry from .tar file: {:?}", v))?
+                    .map(PathBuf::from)
+                    .ok_or_else(|| format!("Link {:?} in .tar file has no target", path))
+            };
+
+            // Directories are created as needed for the files within them
+            let kind = match entry.header().entry_type() {
+                EntryType::Regular | EntryType::Continuous => EntryKind::File,
+                EntryType::Symlink => EntryKind::Symlink(link_name()?),
+                EntryType::Link => EntryKind::HardLink(link_name()?),
+                _ => continue,
+            };
+
+            let entry_info = ArchiveEntry {
+                path,
+                kind,
+                mode: entry.header().mode().ok().map(|x| x & 0o777),
+            };
+
+            func(i, None, entry_info, &mut entry)?;
         }
 
         Ok(())
     }
 }
 
-/// Reads the named archive with an archive implementation.
-pub fn read_archive<'a>(name: &str, data: &'a [u8]) -> Result<Box<dyn Archive<'a> + 'a>, String> {
-    if name.ends_with(".zip") {
-        // Decompress a .zip file
-        let archive = UpstreamZipArchive::new(Cursor::new(data))
-            .map_err(|x| format!("Error while reading .zip file: {:?}", x))?;
+/// Selects which entries of an archive are extracted, and where to.
+#[derive(Default)]
+pub struct EntryFilter {
+    /// Number of leading directories to remove from the path of every entry.
+    strip_components: usize,
+    /// Entries to extract, matched after stripping. Everything is extracted if not set.
+    include: Option<GlobSet>,
+    /// Entries to skip, matched after stripping.
+    exclude: Option<GlobSet>,
+}
+
+impl EntryFilter {
+    /// Builds a filter, failing if any of the glob patterns are invalid.
+    pub fn new(
+        strip_components: usize,
+        include: &[String],
+        exclude: &[String],
+    ) -> Result<EntryFilter, String> {
+        Ok(EntryFilter {
+            strip_components,
+            include: build_glob_set(include)?,
+            exclude: build_glob_set(exclude)?,
+        })
+    }
+
+    /// Returns where a (normalised) entry should be extracted to, or `None` if it should
+    /// be skipped.
+    fn apply(&self, path: &Path) -> Option<PathBuf> {
+        let path: PathBuf = path.components().skip(self.strip_components).collect();
+
+        if path.as_os_str().is_empty() {
+            return None;
+        }
+
+        if let Some(include) = &self.include {
+            if !include.is_match(&path) {
+                return None;
+            }
+        }
+
+        if let Some(exclude) = &self.exclude {
+            if exclude.is_match(&path) {
+                return None;
+            }
+        }
+
+        Some(path)
+    }
+}
+
+/// Compiles a list of glob patterns. `*` doesn't match across directories, but `**` does.
+fn build_glob_set(patterns: &[String]) -> Result<Option<GlobSet>, String> {
+    if patterns.is_empty() {
+        return Ok(None);
+    }
+
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        let glob = GlobBuilder::new(pattern)
+            .literal_separator(true)
+            .build()
+            .map_err(|x| format!("Invalid glob pattern {:?}: {:?}", pattern, x))?;
+
+        builder.add(glob);
+    }
+
+    builder
+        .build()
+        .map(Some)
+        .map_err(|x| format!("Unable to compile glob patterns: {:?}", x))
+}
+
+/// Tracks the symlinks produced by an archive. Symlinks may point anywhere once chained
+/// together, so no other path may pass through one.
+#[derive(Default)]
+struct LinkTracker {
+    /// Paths of the symlinks extracted so far.
+    symlinks: HashSet<PathBuf>,
+    /// Directories that checked paths have passed through so far.
+    traversed: HashSet<PathBuf>,
+}
+
+impl LinkTracker {
+    /// Lexically resolves a relative path, like `normalize_path`. Returns `None` if the path
+    /// escapes the directory it is relative to, or passes through a known symlink.
+    fn resolve(&mut self, path: &Path) -> Option<PathBuf> {
+        let mut result = PathBuf::new();
+
+        for component in path.components() {
+            // Every component after the first is looked up inside the current path
+            if !result.as_os_str().is_empty() {
+                if self.symlinks.contains(&result) {
+                    return None;
+                }
+
+                self.traversed.insert(result.clone());
+            }
+
+            match component {
+                Component::Normal(v) => result.push(v),
+                Component::CurDir => {}
+                Component::ParentDir => {
+                    if !result.pop() {
+                        return None;
+                    }
+                }
+                Component::RootDir | Component::Prefix(_) => return None,
+            }
+        }
+
+        Some(result)
+    }
+
+    /// Records a new symlink. Fails if an earlier path has already passed through it.
+    fn add_symlink(&mut self, path: &Path) -> bool {
+        if self.traversed.contains(path) {
+            return false;
+        }
+
+        self.symlinks.insert(path.to_path_buf());
+        true
+    }
+}
+
+/// Wraps another archive, ensuring that every entry (and the target of every link) stays
+/// within the directory that the archive is extracted into. Entries are also stripped
+/// and filtered here, so that the final layout is what gets checked.
+struct CheckedArchive {
+    archive: Box<dyn Archive>,
+    filter: EntryFilter,
+    links: LinkTracker,
+}
+
+impl CheckedArchive {
+    /// Creates a wrapper for an archive.
+    fn new(archive: Box<dyn Archive>, filter: EntryFilter) -> CheckedArchive {
+        CheckedArchive {
+            archive,
+            filter,
+            links: LinkTracker::default(),
+        }
+    }
+
+    /// Normalises and filters the paths of an entry, failing if any of them are unsafe.
+    /// Returns `None` if the entry should be skipped.
+    fn check_entry(
+        filter: &EntryFilter,
+        links: &mut LinkTracker,
+        entry: ArchiveEntry,
+    ) -> Result<Option<ArchiveEntry>, String> {
+        let path = match normalize_path(&entry.path) {
+            Some(v) if v.as_os_str().is_empty() => {
+                return Err(format!("Archive entry {:?} has an empty path.", entry.path))
+            }
+            Some(v) => v,
+            None => {
+                return Err(format!(
+                    "Archive entry {:?} would be extracted outside of the install directory.",
+                    entry.path
+                ))
+            }
+        };
+
+        let path = match filter.apply(&path) {
+            Some(v) => v,
+            None => {
+                info!("Skipping {:?}", entry.path);
+                return Ok(None);
+            }
+        };
+
+        // Writing through a symlink could land anywhere
+        if links.resolve(&path).is_none() {
+            return Err(format!(
+                "Archive entry {:?} would be extracted outside of the install directory.",
+                entry.path
+            ));
+        }
+
+        let entry_path = &entry.path;
+        let outside = |target: &Path| {
+            format!(
+                "Archive entry {:?} links to {:?}, which is outside of the install directory.",
+                entry_path, target
+            )
+        };
+
+        let kind = match entry.kind {
+            EntryKind::File => EntryKind::File,
+            EntryKind::Symlink(target) => {
+                // Symlinks are resolved relative to their own directory
+                let parent = path.parent().unwrap_or_else(|| Path::new(""));
+                if links.resolve(&parent.join(&target)).is_none() || !links.add_symlink(&path) {
+                    return Err(outside(&target));
+                }
+
+                EntryKind::Symlink(target)
+            }
+            EntryKind::HardLink(target) => match normalize_path(&target) {
+                Some(v) if !v.as_os_str().is_empty() => match filter.apply(&v) {
+                    Some(v) => match links.resolve(&v) {
+                        Some(v) => EntryKind::HardLink(v),
+                        None => return Err(outside(&target)),
+                    },
+                    None => {
+                        return Err(format!(
+                            "Archive entry {:?} links to {:?}, which isn't extracted.",
+                            entry.path, target
+                        ))
+                    }
+                },
+                _ => return Err(outside(&target)),
+            },
+        };
+
+        Ok(Some(ArchiveEntry {
+            path,
+            kind,
+            mode: entry.mode,
+        }))
+    }
+}
+
+impl Archive for CheckedArchive {
+    fn for_each(
+        &mut self,
+        func: &mut dyn FnMut(
+            usize,
+            Option<usize>,
+            ArchiveEntry,
+            &mut dyn Read,
+        ) -> Result<(), String>,
+    ) -> Result<(), String> {
+        let filter = &self.filter;
+        let links = &mut self.links;
+
+        self.archive.for_each(
+            &mut |i, max, entry, data| match CheckedArchive::check_entry(filter, links, entry)? {
+                Some(entry) => func(i, max, entry, data),
+                None => Ok(()),
+            },
+        )
+    }
+}
+
+/// Set in the attributes of .7z entries which record a Unix mode.
+const FILE_ATTRIBUTE_UNIX_EXTENSION: u32 = 0x8000;
+
+struct SevenZArchive {
+    archive: SevenZReader<BufReader<File>>,
+}
+
+impl Archive for SevenZArchive {
+    fn for_each(
+        &mut self,
+        func: &mut dyn FnMut(
+            usize,
+            Option<usize>,
+            ArchiveEntry,
+            &mut dyn Read,
+        ) -> Result<(), String>,
+    ) -> Result<(), String> {
+        let max = self.archive.archive().files.len();
+        let mut i = 0;
+
+        // Errors from the callback can't pass through the upstream iterator, so stash them
+        let mut error = None;
+
+        self.archive
+            .for_each_entries(|entry, data| {
+                // Stopping only ends the current folder, so skip anything after an error
+                if error.is_some() {
+                    return Ok(false);
+                }
+
+                let index = i;
+                i += 1;
+
+                if entry.is_directory() {
+                    return Ok(true);
+                }
+
+                // Names may use either separator, depending on where the archive was built
+                let path = PathBuf::from(entry.name().replace('\\', "/"));
+
+                // Archivers on Unix store the mode in the upper half of the attributes
+                let mode = if entry.has_windows_attributes
+                    && entry.windows_attributes() & FILE_ATTRIBUTE_UNIX_EXTENSION != 0
+                {
+                    Some(entry.windows_attributes() >> 16)
+                } else {
+                    None
+                };
+
+                let result = ArchiveEntry::from_unix_mode(path, mode, data)
+                    .and_then(|entry| func(index, Some(max), entry, data));
+
+                match result {
+                    Ok(()) => Ok(true),
+                    Err(v) => {
+                        error.get_or_insert(v);
+                        Ok(false)
+                    }
+                }
+            })
+            .map_err(|x| format!("Error while reading .7z file: {:?}", x))?;
+
+        match error {
+            Some(v) => Err(v),
+            None => Ok(()),
+        }
+    }
+}
+
+/// A single, uncompressed file (such as an AppImage) that is installed as-is.
+struct RawArchive {
+    path: PathBuf,
+    data: BufReader<File>,
+}
+
+impl Archive for RawArchive {
+    fn for_each(
+        &mut self,
+        func: &mut dyn FnMut(
+            usize,
+            Option<usize>,
+            ArchiveEntry,
+            &mut dyn Read,
+        ) -> Result<(), String>,
+    ) -> Result<(), String> {
+        // Raw files (e.g. AppImages) are generally meant to be run
+        let entry = ArchiveEntry {
+            path: self.path.clone(),
+            kind: EntryKind::File,
+            mode: Some(0o755),
+        };
+
+        func(0, Some(1), entry, &mut self.data)
+    }
+}
+
+/// A supported archive format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    Zip,
+    SevenZ,
+    Tar,
+    TarXz,
+    TarGz,
+    TarBz2,
+    TarZst,
+    /// A single file (such as an AppImage), installed as-is.
+    Raw,
+}
+
+impl ArchiveFormat {
+    /// Parses a format name, as given in a package's `format` option.
+    pub fn from_name(name: &str) -> Option<ArchiveFormat> {
+        match name {
+            "zip" => Some(ArchiveFormat::Zip),
+            "7z" => Some(ArchiveFormat::SevenZ),
+            "tar" => Some(ArchiveFormat::Tar),
+            "tar.xz" => Some(ArchiveFormat::TarXz),
+            "tar.gz" => Some(ArchiveFormat::TarGz),
+            "tar.bz2" => Some(ArchiveFormat::TarBz2),
+            "tar.zst" => Some(ArchiveFormat::TarZst),
+            "raw" => Some(ArchiveFormat::Raw),
+            _ => None,
+        }
+    }
+
+    /// Guesses a format from the suffix of a file name.
+    fn from_suffix(name: &str) -> Option<ArchiveFormat> {
+        const SUFFIXES: &[(&str, ArchiveFormat)] = &[
+            (".AppImage", ArchiveFormat::Raw),
+            (".zip", ArchiveFormat::Zip),
+            (".7z", ArchiveFormat::SevenZ),
+            (".tar", ArchiveFormat::Tar),
+            (".tar.xz", ArchiveFormat::TarXz),
+            (".tar.gz", ArchiveFormat::TarGz),
+            (".tgz", ArchiveFormat::TarGz),
+            (".tar.bz2", ArchiveFormat::TarBz2),
+            (".tar.zst", ArchiveFormat::TarZst),
+        ];
+
+        SUFFIXES
+            .iter()
+            .find(|(suffix, _)| name.ends_with(suffix))
+            .map(|(_, format)| *format)
+    }
+
+    /// Detects a format from the first bytes of a file. Compressed streams are assumed to
+    /// contain a .tar file, and executables (such as AppImages) are installed as-is.
+    fn from_magic(header: &[u8]) -> Option<ArchiveFormat> {
+        const MAGIC: &[(&[u8], ArchiveFormat)] = &[
+            (b"PK\x03\x04", ArchiveFormat::Zip),
+            (b"PK\x05\x06", ArchiveFormat::Zip),
+            (b"7z\xBC\xAF\x27\x1C", ArchiveFormat::SevenZ),
+            (b"\xFD7zXZ\x00", ArchiveFormat::TarXz),
+            (b"\x1F\x8B", ArchiveFormat::TarGz),
+            (b"BZh", ArchiveFormat::TarBz2),
+            (b"\x28\xB5\x2F\xFD", ArchiveFormat::TarZst),
+            (b"\x7FELF", ArchiveFormat::Raw),
+        ];
+
+        let format = MAGIC
+            .iter()
+            .find(|(magic, _)| header.starts_with(magic))
+            .map(|(_, format)| *format);
+
+        // Uncompressed .tar files have their magic after the first header's fields
+        if format.is_none() && header.get(257..262) == Some(&b"ustar"[..]) {
+            return Some(ArchiveFormat::Tar);
+        }
+
+        format
+    }
+}
+
+/// Reads the named archive with an archive implementation. Contents are streamed from
+/// the specified file as they are extracted.
+///
+/// The format is detected from the file's contents, falling back to its name, unless
+/// `format` is specified. If `raw_path` is specified, or the file is a single executable
+/// (such as an AppImage), the file is passed through untouched and placed at `raw_path`
+/// (or its own name) relative to the install directory.
+///
+/// Entries of archives are stripped and selected by `filter`. Entries which would be
+/// extracted outside of the install directory (such as absolute paths, or paths
+/// containing `..`) fail the extraction.
+pub fn read_archive(
+    name: &str,
+    mut file: File,
+    raw_path: Option<&str>,
+    format: Option<&str>,
+    filter: EntryFilter,
+) -> Result<Box<dyn Archive>, String> {
+    let len = file
+        .metadata()
+        .map_err(|x| format!("Unable to read archive metadata: {:?}", x))?
+        .len();
+
+    let format = match (raw_path, format) {
+        (Some(_), _) => ArchiveFormat::Raw,
+        (None, Some(format)) => ArchiveFormat::from_name(format)
+            .ok_or_else(|| format!("Unknown archive format {:?}.", format))?,
+        (None, None) => detect_format(name, &mut file)?,
+    };
+
+    info!("Reading {:?} as {:?}", name, format);
+
+    let data = BufReader::new(file);
+
+    let archive: Box<dyn Archive> = match format {
+        ArchiveFormat::Raw => {
+            // Raw files are placed exactly where they were asked to be
+            return Ok(Box::new(CheckedArchive::new(
+                Box::new(RawArchive {
+                    path: PathBuf::from(raw_path.unwrap_or(name)),
+                    data,
+                }),
+                EntryFilter::default(),
+            )));
+        }
+        ArchiveFormat::Zip => {
+            // Decompress a .zip file
+            let archive = UpstreamZipArchive::new(data)
+                .map_err(|x| format!("Error while reading .zip file: {:?}", x))?;
+
+            Box::new(ZipArchive { archive })
+        }
+        ArchiveFormat::SevenZ => {
+            // Decompress a .7z file
+            let archive = SevenZReader::new(data, len, Password::empty())
+                .map_err(|x| format!("Error while reading .7z file: {:?}", x))?;
+
+            Box::new(SevenZArchive { archive })
+        }
+        ArchiveFormat::Tar => tar_archive(Box::new(data)),
+        ArchiveFormat::TarXz => tar_archive(Box::new(XzDecoder::new(data))),
+        ArchiveFormat::TarGz => tar_archive(Box::new(GzDecoder::new(data))),
+        ArchiveFormat::TarBz2 => tar_archive(Box::new(BzDecoder::new(data))),
+        ArchiveFormat::TarZst => {
+            let decoder = ZstdDecoder::new(data)
+                .map_err(|x| format!("Error while reading .tar.zst file: {:?}", x))?;
+
+            tar_archive(Box::new(decoder))
+        }
+    };
+
+    Ok(Box::new(CheckedArchive::new(archive, filter)))
+}
+
+/// Detects the format of a file from its contents, using its name as a hint if the
+/// contents aren't recognised. The file is left positioned at its start.
+fn detect_format(name: &str, file: &mut File) -> Result<ArchiveFormat, String> {
+    let mut header = Vec::with_capacity(512);
+    file.by_ref()
+        .take(512)
+        .read_to_end(&mut header)
+        .map_err(|x| format!("Unable to read archive header: {:?}", x))?;
+    file.seek(SeekFrom::Start(0))
+        .map_err(|x| format!("Unable to read archive header: {:?}", x))?;
+
+    let hint = ArchiveFormat::from_suffix(name);
+
+    match (ArchiveFormat::from_magic(&header), hint) {
+        (Some(format), Some(hint)) if format != hint => {
+            warn!(
+                "{:?} looks like {:?} rather than {:?} from its name",
+                name, format, hint
+            );
+            Ok(format)
+        }
+        (Some(format), _) => Ok(format),
+        (None, Some(hint)) => Ok(hint),
+        (None, None) => Err(format!("No decompression handler for {:?}.", name)),
+    }
+}
+
+/// Wraps a decompressed stream in a .tar reader.
+fn tar_archive(decompressed_contents: Box<dyn Read>) -> Box<dyn Archive> {
+    Box::new(TarArchive {
+        archive: UpstreamTarArchive::new(decompressed_contents),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::{Cursor, Write};
+
+    use tar::{Builder, Header};
+    use zip::write::FileOptions;
+    use zip::ZipWriter;
+
+    /// Builds a .tar file from (name, type, link target) entries. Names are written into
+    /// the header directly, as the builder refuses to create unsafe paths.
+    fn build_tar(entries: &[(&str, EntryType, &str)]) -> Vec<u8> {
+        let mut builder = Builder::new(Vec::new());
+
+        for (name, entry_type, link) in entries {
+            let data: &[u8] = match entry_type {
+                EntryType::Regular => b"contents",
+                _ => b"",
+            };
+
+            let mut header = Header::new_gnu();
+            header.as_old_mut().name[..name.len()].copy_from_slice(name.as_bytes());
+            header.as_old_mut().linkname[..link.len()].copy_from_slice(link.as_bytes());
+            header.set_entry_type(*entry_type);
+            header.set_mode(0o644);
+            header.set_size(data.len() as u64);
+            header.set_cksum();
+
+            builder.append(&header, data).unwrap();
+        }
+
+        builder.into_inner().unwrap()
+    }
+
+    /// Asserts that reading an archive failed because of an unsafe path.
+    fn assert_unsafe(result: Result<Vec<PathBuf>, String>) {
+        match result {
+            Err(v) => assert!(v.contains("outside of the install directory"), "{}", v),
+            Ok(v) => panic!("Unsafe archive was accepted: {:?}", v),
+        }
+    }
+
+    /// Extracts the paths of every entry from an archive's contents.
+    fn read_paths(name: &str, contents: &[u8]) -> Result<Vec<PathBuf>, String> {
+        read_filtered_paths(name, contents, EntryFilter::default())
+    }
+
+    /// Extracts the paths of the selected entries from an archive's contents.
+    fn read_filtered_paths(
+        name: &str,
+        contents: &[u8],
+        filter: EntryFilter,
+    ) -> Result<Vec<PathBuf>, String> {
+        let path = std::env::temp_dir().join(format!(
+            "liftinstall-archive-test-{}-{}",
+            std::process::id(),
+            name
+        ));
+        std::fs::write(&path, contents).unwrap();
+        let file = File::open(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+
+        let mut paths = Vec::new();
+        read_archive(name, file, None, None, filter)?.for_each(&mut |_, _, entry, _| {
+            paths.push(entry.path);
+            Ok(())
+        })?;
+
+        Ok(paths)
+    }
+
+    #[test]
+    fn normalizes_paths() {
+        assert_eq!(
+            normalize_path(Path::new("./a/b/../c.txt")),
+            Some(PathBuf::from("a/c.txt"))
+        );
+        assert_eq!(normalize_path(Path::new("a/../../b")), None);
+        assert_eq!(normalize_path(Path::new("/etc/passwd")), None);
+    }
+
+    #[test]
+    fn accepts_safe_tar_entries() {
+        let tar = build_tar(&[
+            ("./bin/app", EntryType::Regular, ""),
+            ("bin/../lib/app.so", EntryType::Regular, ""),
+            ("bin/link", EntryType::Symlink, "../lib/app.so"),
+            ("bin/hard", EntryType::Link, "./bin/app"),
+        ]);
+
+        assert_eq!(
+            read_paths("safe.tar", &tar),
+            Ok(vec![
+                PathBuf::from("bin/app"),
+                PathBuf::from("lib/app.so"),
+                PathBuf::from("bin/link"),
+                PathBuf::from("bin/hard"),
+            ])
+        );
+    }
+
+    #[test]
+    fn rejects_tar_path_traversal() {
+        let tar = build_tar(&[("../evil", EntryType::Regular, "")]);
+        assert_unsafe(read_paths("traversal.tar", &tar));
+
+        let tar = build_tar(&[("bin/../../evil", EntryType::Regular, "")]);
+        assert_unsafe(read_paths("nested-traversal.tar", &tar));
+    }
+
+    #[test]
+    fn rejects_tar_absolute_paths() {
+        let tar = build_tar(&[("/tmp/evil", EntryType::Regular, "")]);
+        assert_unsafe(read_paths("absolute.tar", &tar));
+    }
+
+    #[test]
+    fn rejects_tar_links_outside_root() {
+        let tar = build_tar(&[("bin/link", EntryType::Symlink, "../../etc/passwd")]);
+        assert_unsafe(read_paths("symlink.tar", &tar));
+
+        let tar = build_tar(&[("link", EntryType::Symlink, "/etc/passwd")]);
+        assert_unsafe(read_paths("absolute-symlink.tar", &tar));
+
+        let tar = build_tar(&[("hard", EntryType::Link, "../etc/passwd")]);
+        assert_unsafe(read_paths("hardlink.tar", &tar));
+    }
+
+    #[test]
+    fn rejects_zip_path_traversal() {
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        zip.start_file("../evil", FileOptions::default()).unwrap();
+        zip.write_all(b"contents").unwrap();
+        let zip = zip.finish().unwrap().into_inner();
+
+        assert_unsafe(read_paths("traversal.zip", &zip));
+    }
+
+    #[test]
+    fn strips_and_filters_entries() {
+        let tar = build_tar(&[
+            ("top", EntryType::Directory, ""),
+            ("top/README", EntryType::Regular, ""),
+            ("top/bin/app", EntryType::Regular, ""),
+            ("top/bin/app.debug", EntryType::Regular, ""),
+            ("top/bin/tools/helper", EntryType::Regular, ""),
+            ("top/share/doc.txt", EntryType::Regular, ""),
+        ]);
+
+        let filter = EntryFilter::new(
+            1,
+            &["README".to_string(), "bin/*".to_string()],
+            &["**/*.debug".to_string()],
+        )
+        .unwrap();
+
+        assert_eq!(
+            read_filtered_paths("filtered.tar", &tar, filter),
+            Ok(vec![PathBuf::from("README"), PathBuf::from("bin/app")])
+        );
+    }
+
+    #[test]
+    fn rejects_links_escaping_after_stripping() {
+        let tar = build_tar(&[("top/link", EntryType::Symlink, "../outside")]);
+        let filter = EntryFilter::new(1, &[], &[]).unwrap();
+
+        assert_unsafe(read_filtered_paths("stripped-symlink.tar", &tar, filter));
+    }
 
-        Ok(Box::new(ZipArchive { archive }))
-    } else if name.ends_with(".tar.xz") {
-        // Decompress a .tar.xz file
-        let mut decompresser = XzDecoder::new(data);
-        let mut decompressed_data = Vec::new();
-        decompresser
-            .read_to_end(&mut decompressed_data)
-            .map_err(|x| format!("Failed to decompress data: {:?}", x))?;
+    #[test]
+    fn rejects_tar_symlink_chains() {
+        // Each link is harmless on its own, but "y" resolves to the parent of the root
+        let tar = build_tar(&[
+            ("d/x", EntryType::Symlink, ".."),
+            ("y", EntryType::Symlink, "d/x/.."),
+        ]);
+        assert_unsafe(read_paths("symlink-chain.tar", &tar));
 
-        let decompressed_contents: Box<dyn Read> = Box::new(Cursor::new(decompressed_data));
+        // The same, with the links in the opposite order
+        let tar = build_tar(&[
+            ("y", EntryType::Symlink, "d/x/.."),
+            ("d/x", EntryType::Symlink, ".."),
+        ]);
+        assert_unsafe(read_paths("reversed-symlink-chain.tar", &tar));
 
-        let tar = UpstreamTarArchive::new(decompressed_contents);
+        let tar = build_tar(&[
+            ("link", EntryType::Symlink, "."),
+            ("h", EntryType::Link, "link/file"),
+        ]);
+        assert_unsafe(read_paths("hardlink-through-symlink.tar", &tar));
 
-        Ok(Box::new(TarArchive { archive: tar }))
-    } else {
-        Err(format!("No decompression handler for {:?}.", name))
+        let tar = build_tar(&[
+            ("link", EntryType::Symlink, "."),
+            ("link/file", EntryType::Regular, ""),
+        ]);
+        assert_unsafe(read_paths("file-through-symlink.tar", &tar));
     }
 }
@@ -7,8 +7,21 @@ use toml::de::Error as TomlError;
 
 use serde_json::{self, Error as SerdeError};
 
+use crate::archives::EntryFilter;
+
+use crate::sources::cache::{CacheEntry, ReleaseCache};
 use crate::sources::get_by_name;
-use crate::sources::types::Release;
+use crate::sources::types::{Listing, Release, Version};
+
+use crate::logging::LoggingErrors;
+
+use sha2::{Digest, Sha256};
+
+use regex::Regex;
+
+use std::collections::HashMap;
+use std::env::consts::OS;
+use std::iter::once;
 
 /// Description of the source of a package.
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -17,6 +30,46 @@ pub struct PackageSource {
     #[serde(rename = "match")]
     pub match_regex: String,
     pub config: toml::Value,
+    /// Relative path to install a single-file (non-archive) asset to, such as an AppImage.
+    #[serde(default)]
+    pub raw_path: Option<String>,
+    /// Format of the matched file (e.g. "zip", "tar.xz" or "raw"), if it can't be detected
+    /// from its contents or name.
+    #[serde(default)]
+    pub format: Option<String>,
+    /// Regex whose first capture group extracts the version from the name of the
+    /// matched file, overriding the version reported by the source.
+    #[serde(default)]
+    pub version_regex: Option<String>,
+    /// How long, in seconds, a cached listing of releases may be reused without asking
+    /// the source again.
+    #[serde(default = "default_cache_ttl")]
+    pub cache_ttl: u64,
+    /// Number of leading directories to strip from the files in the package's archive,
+    /// such as a top-level folder named after the release.
+    #[serde(default)]
+    pub strip_components: usize,
+    /// Glob patterns (e.g. "bin/**") of files to extract, after stripping. Everything is
+    /// extracted if empty.
+    #[serde(default)]
+    pub include: Vec<String>,
+    /// Glob patterns of files not to extract, after stripping.
+    #[serde(default)]
+    pub exclude: Vec<String>,
+}
+
+fn default_cache_ttl() -> u64 {
+    600
+}
+
+/// Controls how the releases of a package are fetched.
+#[derive(Clone, Default)]
+pub struct FetchOptions {
+    pub cache: Option<ReleaseCache>,
+    /// If a cached listing younger than the source's `cache_ttl` can be used as-is
+    pub reuse_fresh: bool,
+    /// Access tokens for source APIs, by source name
+    pub tokens: HashMap<String, String>,
 }
 
 /// Describes if/how a shortcut should be built for a package.
@@ -53,6 +106,10 @@ pub struct PackageDescription {
     pub icon: Option<String>,
     pub default: Option<bool>,
     pub source: PackageSource,
+    /// Sources to fall back to, in order, if `source` can't be reached. Files are installed
+    /// using the `raw_path`, `format` and extraction options of the source that served them.
+    #[serde(default)]
+    pub mirrors: Vec<PackageSource>,
     #[serde(default)]
     pub shortcuts: Vec<PackageShortcut>,
     #[serde(default)]
@@ -61,6 +118,13 @@ pub struct PackageDescription {
     pub is_new: Option<bool>,
     #[serde(default)]
     pub extended_description: Option<PackageExtendedDescription>,
+    /// Base64 encoded minisign (ed25519) public key. If set, this package's files must have
+    /// a valid detached signature published alongside them.
+    #[serde(default)]
+    pub signing_key: Option<String>,
+    /// Number of previous versions of this package to keep on disk for rolling back to.
+    #[serde(default)]
+    pub keep_versions: usize,
 }
 
 /// Configuration for validating the JWT token
@@ -125,14 +189,187 @@ impl Config {
     }
 }
 
+impl PackageDescription {
+    /// Fetches releases from the first of this package's sources that can be reached,
+    /// returning the source that was used along with them. Configuration errors in any
+    /// source are returned before anything is fetched, as trying a mirror won't fix them.
+    pub fn get_current_releases(
+        &self,
+        options: &FetchOptions,
+    ) -> Result<(&PackageSource, Vec<Release>), String> {
+        let sources = once(&self.source).chain(self.mirrors.iter());
+
+        for source in sources.clone() {
+            source.check_config(options)?;
+        }
+
+        let mut errors = Vec::new();
+
+        for (index, source) in sources.enumerate() {
+            match source.get_current_releases(options) {
+                Ok(releases) => {
+                    if index > 0 {
+                        info!(
+                            "Using mirror #{} ({}) for {:?}",
+                            index, source.name, self.name
+                        );
+                    }
+
+                    return Ok((source, releases));
+                }
+                Err(v) => {
+                    warn!(
+                        "Source #{} ({}) for {:?} failed: {}",
+                        index, source.name, self.name, v
+                    );
+                    errors.push(v);
+                }
+            }
+        }
+
+        if errors.len() == 1 {
+            Err(errors.remove(0))
+        } else {
+            Err(format!(
+                "All sources for {:?} failed:\n{}",
+                self.name,
+                errors.join("\n")
+            ))
+        }
+    }
+}
+
 impl PackageSource {
-    /// Fetches releases for a given package
-    pub fn get_current_releases(&self) -> Result<Vec<Release>, String> {
+    /// Fetches releases for a given package. If the options have a cache, the listing is
+    /// revalidated against it, and if `reuse_fresh` is set a listing younger than
+    /// `cache_ttl` is used without contacting the source at all.
+    pub fn get_current_releases(&self, options: &FetchOptions) -> Result<Vec<Release>, String> {
+        let package_handler = match get_by_name(&self.name) {
+            Some(v) => v,
+            _ => return Err(format!("Handler {} not found", self.name)),
+        };
+
+        let config = self.config_with_token(options);
+        let cache = options.cache.as_ref();
+
+        let key = self.cache_key();
+        let cached = cache.and_then(|x| x.load(&key));
+
+        let mut releases = match cached {
+            Some(entry) if options.reuse_fresh && entry.age() < self.cache_ttl => {
+                info!(
+                    "Using cached releases from {} ({}s old)",
+                    self.name,
+                    entry.age()
+                );
+                entry.releases
+            }
+            cached => {
+                let etag = cached.as_ref().and_then(|x| x.etag.as_deref());
+
+                let (releases, etag) =
+                    match package_handler.get_releases_if_changed(&config, etag)? {
+                        Listing::Modified { releases, etag } => (releases, etag),
+                        Listing::NotModified => {
+                            info!("Cached releases from {} are still current", self.name);
+                            let entry = cached.log_expect("Only cached listings can be unmodified");
+                            (entry.releases, entry.etag)
+                        }
+                    };
+
+                if let Some(cache) = cache {
+                    let entry = CacheEntry::new(releases, etag);
+                    cache.store(&key, &entry);
+                    entry.releases
+                } else {
+                    releases
+                }
+            }
+        };
+
+        if let Some(version_regex) = &self.version_regex {
+            let match_regex = self.compile_match_regex()?;
+            let version_regex = Regex::new(&version_regex.replace("#PLATFORM#", OS))
+                .map_err(|v| format!("An error occurred while compiling regex: {:?}", v))?;
+
+            for release in releases.iter_mut() {
+                let version = release
+                    .files
+                    .iter()
+                    .filter(|x| match_regex.is_match(&x.name))
+                    .filter_map(|x| version_regex.captures(&x.name)?.get(1))
+                    .find_map(|x| Version::parse(x.as_str()));
+
+                match version {
+                    Some(v) => release.version = v,
+                    None => warn!(
+                        "Unable to extract version from files of release {}",
+                        release.version
+                    ),
+                }
+            }
+        }
+
+        Ok(releases)
+    }
+
+    /// Checks that this source's handler exists and that its configuration and regexes
+    /// are valid.
+    pub fn check_config(&self, options: &FetchOptions) -> Result<(), String> {
         let package_handler = match get_by_name(&self.name) {
             Some(v) => v,
             _ => return Err(format!("Handler {} not found", self.name)),
         };
 
-        package_handler.get_current_releases(&self.config)
+        package_handler.check_config(&self.config_with_token(options))?;
+        self.compile_match_regex()?;
+
+        if let Some(version_regex) = &self.version_regex {
+            Regex::new(&version_regex.replace("#PLATFORM#", OS))
+                .map_err(|v| format!("An error occurred while compiling regex: {:?}", v))?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the access token needed to download a file from this source, if any.
+    pub fn download_token(&self, url: &str, options: &FetchOptions) -> Option<String> {
+        get_by_name(&self.name)?.download_token(&self.config_with_token(options), url)
+    }
+
+    /// Returns the source's configuration, with any stored access token for the source
+    /// added to it.
+    fn config_with_token(&self, options: &FetchOptions) -> toml::Value {
+        let mut config = self.config.clone();
+
+        if let (Some(token), Some(table)) = (options.tokens.get(&self.name), config.as_table_mut())
+        {
+            if !table.contains_key("token") {
+                table.insert("token".to_string(), toml::Value::String(token.clone()));
+            }
+        }
+
+        config
+    }
+
+    /// Returns a key identifying this source and its configuration in the release cache.
+    fn cache_key(&self) -> String {
+        let config = toml::to_string(&self.config).unwrap_or_default();
+        let digest = Sha256::digest(format!("{}\n{}", self.name, config).as_bytes());
+
+        format!("{}-{}", self.name, &format!("{:x}", digest)[..16])
+    }
+
+    /// Compiles the regex used to match files for the current platform.
+    pub fn compile_match_regex(&self) -> Result<Regex, String> {
+        let filtered_regex = self.match_regex.replace("#PLATFORM#", OS);
+
+        Regex::new(&filtered_regex)
+            .map_err(|v| format!("An error occurred while compiling regex: {:?}", v))
+    }
+
+    /// Builds the filter selecting which files of the package's archive are extracted.
+    pub fn entry_filter(&self) -> Result<EntryFilter, String> {
+        EntryFilter::new(self.strip_components, &self.include, &self.exclude)
     }
 }
@@ -17,7 +17,7 @@ use reqwest::header::USER_AGENT;
 use crate::frontend::rest::services::Future as InternalFuture;
 use crate::frontend::rest::services::{default_future, Request, Response, WebService};
 
-use crate::http::{build_async_client, build_client};
+use crate::http::{build_async_client, build_client, rewrite_url};
 
 use crate::config::JWTValidation;
 
@@ -69,7 +69,7 @@ pub fn authenticate_async(
         }
     };
 
-    Box::new(client.post(&url)
+    Box::new(client.post(&rewrite_url(&url))
         .header(USER_AGENT, "liftinstall (j-selby)")
         .header("X-USERNAME", username.clone())
         .header("X-TOKEN", token.clone())
@@ -100,7 +100,7 @@ pub fn authenticate_sync(url: String, username: String, token: String) -> Result
     // Build the HTTP client up
     let client = build_client()?;
 
-    let mut response = client.post(&url)
+    let mut response = client.post(&rewrite_url(&url))
         .header(USER_AGENT, "liftinstall (j-selby)")
         .header("X-USERNAME", username.clone())
         .header("X-TOKEN", token.clone())
@@ -0,0 +1,128 @@
+//! frontend/rest/services/changelog.rs
+//!
+//! The /api/changelog?package=...[&version=...] call returns the release notes of every
+//! release between the installed version of a package and the one that would be installed
+//! (the latest, unless a version is specified).
+
+use crate::frontend::rest::services::default_future;
+use crate::frontend::rest::services::lookup_package;
+use crate::frontend::rest::services::spawn_json;
+use crate::frontend::rest::services::Future;
+use crate::frontend::rest::services::Request;
+use crate::frontend::rest::services::Response;
+use crate::frontend::rest::services::WebService;
+
+use hyper::StatusCode;
+
+use url::form_urlencoded;
+
+use crate::config::FetchOptions;
+use crate::config::PackageDescription;
+use crate::sources::types::Version;
+
+use std::collections::HashMap;
+
+/// The notes for an individual release.
+#[derive(Serialize)]
+struct ReleaseNotes {
+    version: String,
+    title: Option<String>,
+    notes: Option<String>,
+    published_at: Option<String>,
+}
+
+/// Struct used by serde to send a JSON payload to the client.
+#[derive(Serialize)]
+struct ChangelogResponse {
+    package: String,
+    installed_version: Option<String>,
+    target_version: Option<String>,
+    releases: Vec<ReleaseNotes>,
+}
+
+pub fn handle(service: &WebService, req: Request) -> Future {
+    let results = form_urlencoded::parse(req.query().unwrap_or("").as_bytes())
+        .into_owned()
+        .collect::<HashMap<String, String>>();
+
+    let lookup = match lookup_package(service, &results) {
+        Some(v) => v,
+        None => return default_future(Response::new().with_status(StatusCode::NotFound)),
+    };
+
+    let version = results
+        .get("version")
+        .filter(|x| x.as_str() != "latest")
+        .cloned();
+
+    // Polling the source is blocking, so don't hold up the server while doing so
+    spawn_json(move || {
+        build_changelog(
+            &lookup.package,
+            lookup.installed.map(|x| x.version),
+            version,
+            &lookup.options,
+        )
+    })
+}
+
+/// Collects the notes of releases newer than the installed version, up to and including
+/// the target version, newest first.
+fn build_changelog(
+    package: &PackageDescription,
+    installed: Option<Version>,
+    version: Option<String>,
+    options: &FetchOptions,
+) -> Result<ChangelogResponse, String> {
+    let (source, mut releases) = package.get_current_releases(options)?;
+    let regex = source.compile_match_regex()?;
+    releases.retain(|x| x.files.iter().any(|x| regex.is_match(&x.name)));
+    releases.sort_by(|a, b| b.version.cmp(&a.version));
+
+    let target = match &version {
+        Some(version) => releases
+            .iter()
+            .find(|x| &x.version.to_string() == version)
+            .ok_or_else(|| {
+                format!(
+                    "Version {} of {:?} could not be found.",
+                    version, package.name
+                )
+            })?
+            .version
+            .clone(),
+        None => match releases.first() {
+            Some(v) => v.version.clone(),
+            None => {
+                return Ok(ChangelogResponse {
+                    package: package.name.clone(),
+                    installed_version: installed.map(|x| x.to_string()),
+                    target_version: None,
+                    releases: Vec::new(),
+                })
+            }
+        },
+    };
+
+    let releases = releases
+        .into_iter()
+        .filter(|x| x.version <= target)
+        .filter(|x| match &installed {
+            Some(installed) => &x.version > installed,
+            None => x.version == target,
+        })
+        .map(|x| ReleaseNotes {
+            version: x.version.to_string(),
+            title: x.title,
+            notes: x.notes,
+            published_at: x.published_at,
+        })
+        .collect();
+
+    Ok(ChangelogResponse {
+        package: package.name.clone(),
+        installed_version: installed.map(|x| x.to_string()),
+        target_version: Some(target.to_string()),
+        releases,
+    })
+}
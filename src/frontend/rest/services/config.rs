@@ -16,6 +16,7 @@ use crate::logging::LoggingErrors;
 use crate::config::Config;
 
 use crate::http::build_async_client;
+use crate::http::rewrite_url;
 
 use futures::stream::Stream;
 use futures::Future as _;
@@ -38,7 +39,7 @@ pub fn handle(service: &WebService, _req: Request) -> Future {
     Box::new(
         build_async_client()
             .log_expect("Failed to build async client")
-            .get(&framework_url)
+            .get(&rewrite_url(&framework_url))
             .send()
             .map_err(|x| {
                 error!("HTTP error while downloading configuration file: {:?}", x);
@@ -65,7 +66,7 @@ pub fn handle(service: &WebService, _req: Request) -> Future {
                     .write()
                     .log_expect("Failed to get write lock for framework");
 
-                framework.config = Some(config);
+                framework.set_config(config);
 
                 info!("Configuration file downloaded successfully.");
 
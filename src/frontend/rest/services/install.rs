@@ -27,6 +27,7 @@ pub fn handle(service: &WebService, req: Request) -> Future {
             .collect::<HashMap<String, String>>();
 
         let mut to_install = Vec::new();
+        let mut versions = HashMap::new();
         let mut path: Option<String> = None;
         let mut force_install = false;
         let mut install_desktop_shortcut = false;
@@ -42,6 +43,14 @@ pub fn handle(service: &WebService, req: Request) -> Future {
                 continue;
             }
 
+            // Specific versions are requested as "version:<package name>"
+            if let Some(name) = key.strip_prefix("version:") {
+                if !value.is_empty() {
+                    versions.insert(name.to_string(), value.to_owned());
+                }
+                continue;
+            }
+
             if key == "mode" && value == "force" {
                 force_install = true;
                 continue;
@@ -81,6 +90,7 @@ pub fn handle(service: &WebService, req: Request) -> Future {
 
             if let Err(v) = framework.install(
                 to_install,
+                versions,
                 &sender,
                 new_install,
                 install_desktop_shortcut,
@@ -4,25 +4,31 @@
 
 use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
 
-use crate::installer::{InstallMessage, InstallerFramework};
+use crate::config::{FetchOptions, PackageDescription};
+use crate::installer::{InstallMessage, InstallerFramework, LocalInstallation};
 
 use hyper::server::Service;
 use hyper::{Method, StatusCode};
 
 use crate::logging::LoggingErrors;
 
+use std::collections::HashMap;
 use std::sync::mpsc::{channel, Sender};
 
 use std::thread;
 
-use hyper::header::ContentType;
+use hyper::header::{ContentLength, ContentType};
 
 use futures::future::Future as _;
 use futures::sink::Sink;
+use futures::sync::oneshot;
+
+use serde::Serialize;
 
 mod attributes;
 pub mod authentication;
 mod browser;
+mod changelog;
 mod config;
 mod dark_mode;
 mod default_path;
@@ -30,10 +36,12 @@ mod exit;
 mod install;
 mod installation_status;
 mod packages;
+mod rollback;
 mod static_files;
 mod uninstall;
 mod update_updater;
 mod verify_path;
+mod versions;
 mod view_folder;
 
 /// Expected incoming Request format from Hyper.
@@ -58,6 +66,85 @@ pub fn encapsulate_json(field_name: &str, json: &str) -> String {
     format!("var {} = {};", field_name, json)
 }
 
+/// Runs a blocking function in a thread, responding with its result as JSON. Errors are
+/// returned as plain text with a "Bad Gateway" status, as they generally come from
+/// upstream release sources.
+pub fn spawn_json<F, T>(function: F) -> Future
+where
+    F: FnOnce() -> Result<T, String> + Send + 'static,
+    T: Serialize + Send + 'static,
+{
+    let (sender, receiver) = oneshot::channel();
+
+    thread::spawn(move || {
+        if sender.send(function()).is_err() {
+            error!("Failed to send response to client");
+        }
+    });
+
+    Box::new(
+        receiver
+            .map_err(|_| hyper::Error::Incomplete)
+            .map(|result| match result {
+                Ok(response) => {
+                    let file = serde_json::to_string(&response)
+                        .log_expect("Failed to render JSON response payload");
+
+                    Response::new()
+                        .with_header(ContentLength(file.len() as u64))
+                        .with_header(ContentType::json())
+                        .with_body(file)
+                }
+                Err(v) => {
+                    error!("Error occurred while handling request: {:?}", v);
+
+                    Response::new()
+                        .with_status(StatusCode::BadGateway)
+                        .with_header(ContentLength(v.len() as u64))
+                        .with_header(ContentType::plaintext())
+                        .with_body(v)
+                }
+            }),
+    )
+}
+
+/// A configured package, along with what is needed to poll its sources.
+pub struct PackageLookup {
+    pub package: PackageDescription,
+    pub installed: Option<LocalInstallation>,
+    pub options: FetchOptions,
+}
+
+/// Finds the package named by the `package` query argument, and its installation if it has
+/// one. Returns None if no such package is configured.
+pub fn lookup_package(
+    service: &WebService,
+    query: &HashMap<String, String>,
+) -> Option<PackageLookup> {
+    let framework = service.get_framework_read();
+
+    let package = framework
+        .config
+        .as_ref()?
+        .packages
+        .iter()
+        .find(|x| Some(&x.name) == query.get("package"))?
+        .clone();
+
+    let installed = framework
+        .database
+        .packages
+        .iter()
+        .find(|x| x.name == package.name)
+        .cloned();
+
+    Some(PackageLookup {
+        package,
+        installed,
+        options: framework.fetch_options(),
+    })
+}
+
 /// Streams messages from a specified task to the client in a thread.
 pub fn stream_progress<F: 'static>(function: F) -> Response
 where
@@ -135,16 +222,19 @@ impl Service for WebService {
 
         match (method, path.as_str()) {
             (Method::Get, "/api/attrs") => attributes::handle(self, req),
+            (Method::Get, "/api/changelog") => changelog::handle(self, req),
             (Method::Get, "/api/config") => config::handle(self, req),
             (Method::Get, "/api/dark-mode") => dark_mode::handle(self, req),
             (Method::Get, "/api/default-path") => default_path::handle(self, req),
             (Method::Get, "/api/exit") => exit::handle(self, req),
             (Method::Get, "/api/packages") => packages::handle(self, req),
             (Method::Get, "/api/installation-status") => installation_status::handle(self, req),
+            (Method::Get, "/api/versions") => versions::handle(self, req),
             (Method::Get, "/api/view-local-folder") => view_folder::handle(self, req),
             (Method::Post, "/api/check-auth") => authentication::handle(self, req),
             (Method::Post, "/api/start-install") => install::handle(self, req),
             (Method::Post, "/api/open-browser") => browser::handle(self, req),
+            (Method::Post, "/api/rollback") => rollback::handle(self, req),
             (Method::Post, "/api/uninstall") => uninstall::handle(self, req),
             (Method::Post, "/api/update-updater") => update_updater::handle(self, req),
             (Method::Post, "/api/verify-path") => verify_path::handle(self, req),
@@ -0,0 +1,54 @@
+//! frontend/rest/services/rollback.rs
+//!
+//! The /api/rollback call switches a package back to a previous version, dictated by a
+//! POST request containing the `package` and (optionally) the `version` to switch to.
+
+use crate::frontend::rest::services::stream_progress;
+use crate::frontend::rest::services::Future;
+use crate::frontend::rest::services::Request;
+use crate::frontend::rest::services::WebService;
+
+use crate::logging::LoggingErrors;
+
+use crate::installer::InstallMessage;
+
+use futures::future::Future as _;
+use futures::stream::Stream;
+
+use url::form_urlencoded;
+
+use std::collections::HashMap;
+
+pub fn handle(service: &WebService, req: Request) -> Future {
+    let framework = service.framework.clone();
+
+    Box::new(req.body().concat2().map(move |b| {
+        let mut results = form_urlencoded::parse(b.as_ref())
+            .into_owned()
+            .collect::<HashMap<String, String>>();
+
+        let package = results.remove("package");
+        let version = results.remove("version").filter(|x| !x.is_empty());
+
+        stream_progress(move |sender| {
+            let result = match package {
+                Some(package) => framework
+                    .write()
+                    .log_expect("InstallerFramework has been dirtied")
+                    .rollback(package, version, &sender),
+                None => Err("No package specified to roll back".to_string()),
+            };
+
+            if let Err(v) = result {
+                error!("Rollback error occurred: {:?}", v);
+                if let Err(v) = sender.send(InstallMessage::Error(v)) {
+                    error!("Failed to send rollback error: {:?}", v);
+                }
+            }
+
+            if let Err(v) = sender.send(InstallMessage::EOF) {
+                error!("Failed to send EOF to client: {:?}", v);
+            }
+        })
+    }))
+}
@@ -0,0 +1,99 @@
+//! frontend/rest/services/versions.rs
+//!
+//! The /api/versions?package=... call returns every available version of a package which
+//! has a file matching the package's configuration, along with its local status.
+
+use crate::frontend::rest::services::default_future;
+use crate::frontend::rest::services::lookup_package;
+use crate::frontend::rest::services::spawn_json;
+use crate::frontend::rest::services::Future;
+use crate::frontend::rest::services::Request;
+use crate::frontend::rest::services::Response;
+use crate::frontend::rest::services::WebService;
+
+use hyper::StatusCode;
+
+use url::form_urlencoded;
+
+use crate::config::FetchOptions;
+use crate::config::PackageDescription;
+use crate::installer::LocalInstallation;
+
+use std::collections::HashMap;
+
+/// An individual version of a package which can be installed.
+#[derive(Serialize)]
+struct AvailableVersion {
+    version: String,
+    file: String,
+    installed: bool,
+    pinned: bool,
+    retained: bool,
+}
+
+/// Struct used by serde to send a JSON payload to the client.
+#[derive(Serialize)]
+struct VersionsResponse {
+    package: String,
+    versions: Vec<AvailableVersion>,
+}
+
+pub fn handle(service: &WebService, req: Request) -> Future {
+    let results = form_urlencoded::parse(req.query().unwrap_or("").as_bytes())
+        .into_owned()
+        .collect::<HashMap<String, String>>();
+
+    let lookup = match lookup_package(service, &results) {
+        Some(v) => v,
+        None => return default_future(Response::new().with_status(StatusCode::NotFound)),
+    };
+
+    // Polling the source is blocking, so don't hold up the server while doing so
+    spawn_json(move || list_versions(&lookup.package, lookup.installed.as_ref(), &lookup.options))
+}
+
+/// Polls the package's source for available versions, newest first.
+fn list_versions(
+    package: &PackageDescription,
+    installed: Option<&LocalInstallation>,
+    options: &FetchOptions,
+) -> Result<VersionsResponse, String> {
+    let (source, mut releases) = package.get_current_releases(options)?;
+    let regex = source.compile_match_regex()?;
+    releases.sort_by(|a, b| b.version.cmp(&a.version));
+
+    let mut versions = Vec::new();
+
+    for release in releases {
+        let file = match release.files.iter().find(|x| regex.is_match(&x.name)) {
+            Some(v) => v.name.clone(),
+            None => continue,
+        };
+
+        let (is_installed, pinned, retained) = match installed {
+            Some(installed) => {
+                let is_installed = installed.version == release.version;
+                let retained = installed
+                    .retained_versions
+                    .iter()
+                    .any(|x| x.version == release.version);
+
+                (is_installed, is_installed && installed.pinned, retained)
+            }
+            None => (false, false, false),
+        };
+
+        versions.push(AvailableVersion {
+            version: release.version.to_string(),
+            file,
+            installed: is_installed,
+            pinned,
+            retained,
+        });
+    }
+
+    Ok(VersionsResponse {
+        package: package.name.clone(),
+        versions,
+    })
+}
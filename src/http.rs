@@ -2,10 +2,17 @@
 //!
 //! A simple wrapper around Hyper's HTTP client.
 
-use reqwest::header::CONTENT_LENGTH;
+use reqwest::header::{
+    HeaderMap, CONTENT_LENGTH, CONTENT_RANGE, ETAG, IF_RANGE, LAST_MODIFIED, RANGE,
+};
 
-use std::io::Read;
-use std::time::Duration;
+use std::fs::{read_to_string, File};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::{Duration, UNIX_EPOCH};
+
+use url::Url;
 
 use reqwest::r#async::Client as AsyncClient;
 use reqwest::Client;
@@ -20,6 +27,80 @@ pub fn assert_ssl(url: &str) -> Result<(), String> {
     }
 }
 
+/// A user defined rule which rewrites URLs starting with a prefix, such as to redirect
+/// downloads to a internal mirror.
+#[derive(Debug, Deserialize)]
+pub struct RewriteRule {
+    pub prefix: String,
+    pub replacement: String,
+}
+
+/// Local, user-level settings which aren't part of the remote configuration.
+#[derive(Debug, Deserialize, Default)]
+struct LocalSettings {
+    #[serde(default)]
+    rewrite: Vec<RewriteRule>,
+}
+
+/// Returns the path to the user's local settings file.
+pub fn settings_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|x| x.join("liftinstall").join("settings.toml"))
+}
+
+/// Returns the user's URL rewrite rules, loading them on first use.
+fn rewrite_rules() -> &'static [RewriteRule] {
+    static RULES: OnceLock<Vec<RewriteRule>> = OnceLock::new();
+
+    RULES.get_or_init(|| {
+        let path = match settings_path() {
+            Some(v) if v.exists() => v,
+            _ => return Vec::new(),
+        };
+
+        let settings: LocalSettings = match read_to_string(&path)
+            .map_err(|x| format!("{:?}", x))
+            .and_then(|x| toml::from_str(&x).map_err(|x| format!("{:?}", x)))
+        {
+            Ok(v) => v,
+            Err(v) => {
+                warn!("Unable to read local settings from {:?}: {}", path, v);
+                return Vec::new();
+            }
+        };
+
+        for rule in &settings.rewrite {
+            info!(
+                "Rewriting URLs starting with {:?} to {:?} (from {:?})",
+                rule.prefix, rule.replacement, path
+            );
+        }
+
+        settings.rewrite
+    })
+}
+
+/// Applies the first matching user URL rewrite rule to a URL. This should be used on any
+/// URL before it is requested.
+pub fn rewrite_url(url: &str) -> String {
+    match rewrite_rules().iter().find(|x| url.starts_with(&x.prefix)) {
+        Some(rule) => {
+            let rewritten = format!("{}{}", rule.replacement, &url[rule.prefix.len()..]);
+            info!("Rewrote {:?} to {:?}", url, rewritten);
+            rewritten
+        }
+        None => url.to_string(),
+    }
+}
+
+/// Returns the local path referred to by a `file://` URL, if it is one.
+pub fn local_path(url: &str) -> Option<PathBuf> {
+    if !url.starts_with("file://") {
+        return None;
+    }
+
+    Url::parse(url).ok()?.to_file_path().ok()
+}
+
 /// Builds a customised HTTP client.
 pub fn build_client() -> Result<Client, String> {
     Client::builder()
@@ -36,29 +117,95 @@ pub fn build_async_client() -> Result<AsyncClient, String> {
         .map_err(|x| format!("Unable to build client: {:?}", x))
 }
 
-/// Streams a file from a HTTP server.
-pub fn stream_file<F>(
+/// Streams a file from a HTTP server. Errors returned by the callback abort the transfer.
+pub fn stream_file<F>(url: &str, authorization: Option<String>, callback: F) -> Result<(), String>
+where
+    F: FnMut(Vec<u8>, u64) -> Result<(), String>,
+{
+    stream_file_resumable(url, authorization, 0, None, |_, _| Ok(()), callback)
+}
+
+/// Streams a file from a HTTP server, resuming after the first `offset` bytes using a
+/// Range request.
+///
+/// A partial download is only resumed if `validator` (the ETag or Last-Modified date the
+/// server sent for it) still matches the resource, so that new data is never appended to
+/// a different version of it. Before any data is passed to the callback, `start` is
+/// called with whether the download is being resumed (if not, previously stored data
+/// should be discarded) and the validator of the resource being sent, if any.
+///
+/// The size passed to the callback is always the size of the entire resource.
+pub fn stream_file_resumable<F, S>(
+    url: &str,
+    authorization: Option<String>,
+    offset: u64,
+    validator: Option<&str>,
+    start: S,
+    callback: F,
+) -> Result<(), String>
+where
+    F: FnMut(Vec<u8>, u64) -> Result<(), String>,
+    S: FnMut(bool, Option<&str>) -> Result<(), String>,
+{
+    stream_url(
+        &rewrite_url(url),
+        authorization,
+        offset,
+        validator,
+        start,
+        callback,
+    )
+}
+
+/// Implementation of `stream_file_resumable`, once URL rewrite rules have been applied.
+fn stream_url<F, S>(
     url: &str,
     authorization: Option<String>,
+    offset: u64,
+    validator: Option<&str>,
+    mut start: S,
     mut callback: F,
 ) -> Result<(), String>
 where
-    F: FnMut(Vec<u8>, u64) -> (),
+    F: FnMut(Vec<u8>, u64) -> Result<(), String>,
+    S: FnMut(bool, Option<&str>) -> Result<(), String>,
 {
+    // Local files can't be served over HTTPS, so read them directly
+    if let Some(path) = local_path(url) {
+        return stream_local_file(&path, offset, validator, start, callback);
+    }
+
     assert_ssl(url)?;
 
     let mut client = build_client()?.get(url);
 
-    if let Some(auth) = authorization {
+    if let Some(ref auth) = authorization {
         client = client.header("Authorization", format!("Bearer {}", auth));
     }
 
+    // Without a validator, there is no way to tell if the partial data is still current
+    let offset = match validator {
+        Some(validator) if offset > 0 => {
+            client = client
+                .header(RANGE, format!("bytes={}-", offset))
+                .header(IF_RANGE, validator);
+            offset
+        }
+        _ => 0,
+    };
+
     let mut client = client
         .send()
         .map_err(|x| format!("Failed to GET resource: {:?}", x))?;
 
-    match client.status() {
-        StatusCode::OK => {}
+    let resumed = match client.status() {
+        StatusCode::OK => false,
+        StatusCode::PARTIAL_CONTENT if offset > 0 => true,
+        StatusCode::RANGE_NOT_SATISFIABLE if offset > 0 => {
+            // Our partial data doesn't match what the server has - start over
+            info!("Server rejected resume of download, restarting: {:?}", url);
+            return stream_url(url, authorization, 0, None, start, callback);
+        }
         StatusCode::TOO_MANY_REQUESTS => {
             return Err(
                 "Your token has exceeded the number of daily allowable IP addresses. \
@@ -69,7 +216,7 @@ where
         x => {
             return Err(format!("Bad status code: {:?}.", x));
         }
-    }
+    };
 
     let size = match client.headers().get(CONTENT_LENGTH) {
         Some(ref v) => v
@@ -80,6 +227,38 @@ where
         None => 0,
     };
 
+    let size = if resumed {
+        let range = client
+            .headers()
+            .get(CONTENT_RANGE)
+            .and_then(|x| x.to_str().ok())
+            .and_then(parse_content_range);
+
+        match range {
+            Some((range_start, total)) if range_start == offset => {
+                info!("Resuming download from byte {}: {:?}", offset, url);
+                total.unwrap_or(if size == 0 { 0 } else { size + offset })
+            }
+            _ => {
+                info!(
+                    "Server resumed download from an unexpected position, restarting: {:?}",
+                    url
+                );
+                return stream_url(url, authorization, 0, None, start, callback);
+            }
+        }
+    } else {
+        if offset > 0 {
+            info!(
+                "Server sent the whole resource rather than resuming, restarting: {:?}",
+                url
+            );
+        }
+        size
+    };
+
+    start(resumed, response_validator(client.headers()).as_deref())?;
+
     let mut buf = [0 as u8; 8192];
     loop {
         let len = client
@@ -93,7 +272,82 @@ where
         let buf_copy = &buf[0..len];
         let buf_copy = buf_copy.to_vec();
 
-        callback(buf_copy, size);
+        callback(buf_copy, size)?;
+    }
+
+    Ok(())
+}
+
+/// Returns the validator to resume a response with: a strong ETag if there is one, as
+/// weak ones can't be used for ranges, otherwise the Last-Modified date.
+fn response_validator(headers: &HeaderMap) -> Option<String> {
+    let etag = headers
+        .get(ETAG)
+        .and_then(|x| x.to_str().ok())
+        .filter(|x| !x.starts_with("W/"));
+
+    etag.or_else(|| headers.get(LAST_MODIFIED).and_then(|x| x.to_str().ok()))
+        .map(|x| x.to_string())
+}
+
+/// Parses a `Content-Range` header ("bytes <start>-<end>/<total>"), returning the
+/// position of the first byte sent and the total size, if known.
+fn parse_content_range(value: &str) -> Option<(u64, Option<u64>)> {
+    let (range, total) = value.trim().strip_prefix("bytes ")?.split_once('/')?;
+    let (range_start, _) = range.split_once('-')?;
+
+    Some((range_start.trim().parse().ok()?, total.trim().parse().ok()))
+}
+
+/// Streams a local file in the same manner as `stream_file_resumable`. The modification
+/// time of the file is used as its validator.
+fn stream_local_file<F, S>(
+    path: &Path,
+    offset: u64,
+    validator: Option<&str>,
+    mut start: S,
+    mut callback: F,
+) -> Result<(), String>
+where
+    F: FnMut(Vec<u8>, u64) -> Result<(), String>,
+    S: FnMut(bool, Option<&str>) -> Result<(), String>,
+{
+    let mut file = File::open(path).map_err(|x| format!("Failed to open {:?}: {:?}", path, x))?;
+
+    let metadata = file
+        .metadata()
+        .map_err(|x| format!("Failed to read metadata of {:?}: {:?}", path, x))?;
+    let size = metadata.len();
+
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|x| x.duration_since(UNIX_EPOCH).ok())
+        .map(|x| x.as_nanos().to_string());
+
+    let offset = if offset > 0 && (offset > size || modified.as_deref() != validator) {
+        info!("Partial download doesn't match {:?}, restarting", path);
+        0
+    } else {
+        offset
+    };
+
+    start(offset > 0, modified.as_deref())?;
+
+    file.seek(SeekFrom::Start(offset))
+        .map_err(|x| format!("Failed to seek in {:?}: {:?}", path, x))?;
+
+    let mut buf = [0u8; 8192];
+    loop {
+        let len = file
+            .read(&mut buf)
+            .map_err(|x| format!("Failed to read {:?}: {:?}", path, x))?;
+
+        if len == 0 {
+            break;
+        }
+
+        callback(buf[0..len].to_vec(), size)?;
     }
 
     Ok(())
@@ -23,10 +23,13 @@ use std::process::{exit, Stdio};
 
 use crate::config::BaseAttributes;
 use crate::config::Config;
+use crate::config::FetchOptions;
 
+use crate::sources::cache::{ReleaseCache, CACHE_DIR};
 use crate::sources::types::Version;
 
 use crate::tasks::install::InstallTask;
+use crate::tasks::rollback_pkg::RollbackPackageTask;
 use crate::tasks::uninstall::UninstallTask;
 use crate::tasks::uninstall_global_shortcut::UninstallGlobalShortcutsTask;
 use crate::tasks::DependencyTree;
@@ -36,8 +39,9 @@ use crate::logging::LoggingErrors;
 
 use dirs::home_dir;
 
+use std::collections::HashMap;
 use std::collections::HashSet;
-use std::fs::remove_file;
+use std::fs::{remove_dir_all, remove_file};
 
 use crate::http;
 
@@ -63,27 +67,76 @@ pub struct Credentials {
     pub token: String,
 }
 
+/// The current format of the installation database. Databases before format 1 stored the
+/// IDs of GitHub releases as package versions, rather than versions parsed from their tags.
+const DATABASE_FORMAT: u32 = 1;
+
 /// Metadata about the current installation itself.
 #[derive(Serialize, Deserialize, Clone)]
 pub struct InstallationDatabase {
+    #[serde(default)]
+    pub format: u32,
     pub packages: Vec<LocalInstallation>,
     pub shortcuts: Vec<String>,
     #[serde(default)]
     pub credentials: Credentials,
+    /// Access tokens for release source APIs (e.g. "github"), by source name
+    #[serde(default)]
+    pub source_tokens: HashMap<String, String>,
 }
 
 impl InstallationDatabase {
     /// Creates a new, empty installation database.
     pub fn new() -> InstallationDatabase {
         InstallationDatabase {
+            format: DATABASE_FORMAT,
             packages: Vec::new(),
             shortcuts: Vec::new(),
             credentials: Credentials {
                 username: String::new(),
                 token: String::new(),
             },
+            source_tokens: HashMap::new(),
         }
     }
+
+    /// Upgrades a database written in an older format, using the configuration to find
+    /// where packages came from. The result is written out the next time the database is.
+    pub fn migrate(&mut self, config: &Config) {
+        if self.format >= DATABASE_FORMAT {
+            return;
+        }
+
+        info!(
+            "Migrating installation database from format {} to {}",
+            self.format, DATABASE_FORMAT
+        );
+
+        for package in self.packages.iter_mut() {
+            let is_github = config
+                .packages
+                .iter()
+                .any(|x| x.name == package.name && x.source.name == "github");
+
+            if !is_github {
+                continue;
+            }
+
+            // Release IDs can't be ordered against versions parsed from tags, so keep
+            // them as opaque tags which sort before any other kind of version
+            let versions = Some(&mut package.version)
+                .into_iter()
+                .chain(package.retained_versions.iter_mut().map(|x| &mut x.version));
+
+            for version in versions {
+                if let Version::Integer(id) = *version {
+                    *version = Version::Tag(format!("release-{}", id));
+                }
+            }
+        }
+
+        self.format = DATABASE_FORMAT;
+    }
 }
 
 /// The installer framework contains metadata about packages, what is installable, what isn't,
@@ -120,6 +173,22 @@ pub struct LocalInstallation {
     pub files: Vec<String>,
     /// Absolute paths to generated shortcut files
     pub shortcuts: HashSet<String>,
+    /// Previous versions kept on disk, most recent first
+    #[serde(default)]
+    pub retained_versions: Vec<RetainedVersion>,
+    /// If this package was installed at a specific version, and shouldn't be updated
+    #[serde(default)]
+    pub pinned: bool,
+}
+
+/// A previous version of a package, kept on disk so that it can be switched back to.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RetainedVersion {
+    pub version: Version,
+    /// Relative path to the directory holding this version's files
+    pub path: String,
+    /// Relative paths to files, within `path`
+    pub files: Vec<String>,
 }
 
 macro_rules! declare_messenger_callback {
@@ -174,12 +243,14 @@ impl InstallerFramework {
 
     /// Sends a request for something to be installed.
     /// items: Array of named packages to be installed/kept
+    /// versions: Specific versions to install (and pin) packages at, or "latest" to unpin
     /// messages: Channel used to send progress messages
     /// fresh_install: If the install directory must be empty
     /// force_install: If the install directory should be erased first
     pub fn install(
         &mut self,
         items: Vec<String>,
+        versions: HashMap<String, String>,
         messages: &Sender<InstallMessage>,
         fresh_install: bool,
         create_desktop_shortcuts: bool,
@@ -210,6 +281,7 @@ impl InstallerFramework {
 
         let task = Box::new(InstallTask {
             items,
+            versions,
             uninstall_items,
             fresh_install,
             create_desktop_shortcuts,
@@ -220,6 +292,53 @@ impl InstallerFramework {
 
         info!("Dependency tree:\n{}", tree);
 
+        match tree.execute(self, declare_messenger_callback!(messages)) {
+            Ok(_) => {
+                tree.commit(self);
+                Ok(())
+            }
+            Err(v) => {
+                error!("Install failed, rolling back: {:?}", v);
+                tree.rollback(self);
+
+                // Only rewrite the database if a previous (possibly partial) one exists
+                let has_database = self
+                    .install_path
+                    .as_ref()
+                    .map(|x| x.join("metadata.json").exists())
+                    .unwrap_or(false);
+
+                if has_database {
+                    if let Err(v) = self.save_database() {
+                        error!("Failed to save database after rollback: {:?}", v);
+                    }
+                }
+
+                Err(v)
+            }
+        }
+    }
+
+    /// Sends a request for a package to be switched back to a previous version.
+    /// version: The retained version to switch to, or the most recent one if not specified
+    pub fn rollback(
+        &mut self,
+        name: String,
+        version: Option<String>,
+        messages: &Sender<InstallMessage>,
+    ) -> Result<(), String> {
+        info!("Framework: Rolling back {:?} to {:?}", name, version);
+
+        let task = Box::new(RollbackPackageTask {
+            name,
+            version,
+            install_shortcuts: self.config.is_some(),
+        });
+
+        let mut tree = DependencyTree::build(task);
+
+        info!("Dependency tree:\n{}", tree);
+
         tree.execute(self, declare_messenger_callback!(messages))
             .map(|_x| ())
     }
@@ -259,6 +378,13 @@ impl InstallerFramework {
         remove_file(path.join("metadata.json"))
             .map_err(|x| format!("Failed to delete metadata: {:?}", x))?;
 
+        let cache_path = path.join(CACHE_DIR);
+        if cache_path.exists() {
+            if let Err(v) = remove_dir_all(&cache_path) {
+                warn!("Failed to delete release cache: {:?}", v);
+            }
+        }
+
         // Logging will have to be done later
         self.burn_after_exit = true;
 
@@ -312,6 +438,8 @@ impl InstallerFramework {
             )) {
                 error!("Failed to submit queue message: {:?}", v);
             }
+
+            Ok(())
         })?;
 
         info!("Launching new updater...");
@@ -382,6 +510,52 @@ impl InstallerFramework {
         exit(0);
     }
 
+    /// Downloads the configuration file. The frontend does this itself - this is for use
+    /// from the command line.
+    pub fn download_config(&mut self) -> Result<(), String> {
+        let url = self.base_attributes.target_url.clone();
+        info!("Downloading configuration from {:?}...", url);
+
+        let mut response = http::build_client()?
+            .get(&http::rewrite_url(&url))
+            .send()
+            .map_err(|x| format!("Error while downloading configuration file: {:?}", x))?;
+
+        if !response.status().is_success() {
+            return Err(format!(
+                "Bad status code while downloading configuration file: {:?}",
+                response.status()
+            ));
+        }
+
+        let body = response
+            .text()
+            .map_err(|x| format!("Failed to decode configuration file: {:?}", x))?;
+
+        let config = Config::from_toml_str(&body)
+            .map_err(|x| format!("Failed to parse configuration file: {:?}", x))?;
+
+        self.set_config(config);
+
+        Ok(())
+    }
+
+    /// Returns how releases should be fetched for this installation. The launcher only
+    /// needs a recent listing, rather than the very latest one.
+    pub fn fetch_options(&self) -> FetchOptions {
+        FetchOptions {
+            cache: self.install_path.as_deref().map(ReleaseCache::new),
+            reuse_fresh: self.is_launcher,
+            tokens: self.database.source_tokens.clone(),
+        }
+    }
+
+    /// Sets the configuration, migrating the database against it if required.
+    pub fn set_config(&mut self, config: Config) {
+        self.database.migrate(&config);
+        self.config = Some(config);
+    }
+
     /// Saves the applications database.
     pub fn save_database(&self) -> Result<(), String> {
         // We have to have a install path for us to be able to do anything
@@ -508,3 +682,89 @@ impl InstallerFramework {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"
+        installing_message = "Test"
+
+        [[packages]]
+        name = "github-package"
+        description = "From GitHub"
+        [packages.source]
+        name = "github"
+        match = "^package.zip$"
+        [packages.source.config]
+        repo = "example/package"
+
+        [[packages]]
+        name = "local-package"
+        description = "From a local directory"
+        [packages.source]
+        name = "local"
+        match = "^package.zip$"
+        [packages.source.config]
+        path = "/tmp"
+    "#;
+
+    /// A database from before formats were recorded, when GitHub versions were release IDs.
+    const DATABASE: &str = r#"{
+        "packages": [
+            {
+                "name": "github-package",
+                "version": {"Integer": 1234},
+                "files": [],
+                "shortcuts": [],
+                "retained_versions": [
+                    {"version": {"Integer": 1000}, "path": ".versions/old", "files": []}
+                ]
+            },
+            {
+                "name": "local-package",
+                "version": {"Integer": 5},
+                "files": [],
+                "shortcuts": []
+            }
+        ],
+        "shortcuts": []
+    }"#;
+
+    #[test]
+    fn migrates_github_release_ids() {
+        let config = Config::from_toml_str(CONFIG).unwrap();
+        let mut database: InstallationDatabase = serde_json::from_str(DATABASE).unwrap();
+        assert_eq!(database.format, 0);
+
+        database.migrate(&config);
+
+        assert_eq!(database.format, DATABASE_FORMAT);
+        assert_eq!(
+            database.packages[0].version,
+            Version::Tag("release-1234".to_string())
+        );
+        assert_eq!(
+            database.packages[0].retained_versions[0].version,
+            Version::Tag("release-1000".to_string())
+        );
+
+        // Versions parsed from tags now sort after release IDs
+        assert!(database.packages[0].version < Version::parse("v1.0.0").unwrap());
+
+        // Other sources never used release IDs
+        assert_eq!(database.packages[1].version, Version::Integer(5));
+    }
+
+    #[test]
+    fn migration_is_idempotent() {
+        let config = Config::from_toml_str(CONFIG).unwrap();
+        let mut database: InstallationDatabase = serde_json::from_str(DATABASE).unwrap();
+
+        database.migrate(&config);
+        database.packages[0].version = Version::Integer(1);
+        database.migrate(&config);
+
+        assert_eq!(database.packages[0].version, Version::Integer(1));
+    }
+}
@@ -25,10 +25,15 @@ extern crate toml;
 extern crate regex;
 extern crate semver;
 
+extern crate bzip2;
 extern crate dirs;
+extern crate flate2;
+extern crate globset;
+extern crate sevenz_rust;
 extern crate tar;
 extern crate xz2;
 extern crate zip;
+extern crate zstd;
 
 extern crate fern;
 #[macro_use]
@@ -51,6 +56,9 @@ extern crate jsonwebtoken as jwt;
 
 extern crate base64;
 
+extern crate minisign_verify;
+extern crate sha2;
+
 mod archives;
 mod config;
 mod frontend;
@@ -62,7 +70,7 @@ mod self_update;
 mod sources;
 mod tasks;
 
-use installer::InstallerFramework;
+use installer::{InstallMessage, InstallerFramework};
 
 use logging::LoggingErrors;
 use std::path::PathBuf;
@@ -73,6 +81,8 @@ use clap::Arg;
 use config::BaseAttributes;
 use std::fs;
 use std::process::{exit, Command, Stdio};
+use std::sync::mpsc::channel;
+use std::thread;
 
 const RAW_CONFIG: &str = include_str!(concat!(env!("OUT_DIR"), "/bootstrap.toml"));
 
@@ -96,6 +106,21 @@ fn main() {
                 .help("Launches the specified executable after checking for updates")
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("rollback")
+                .long("rollback")
+                .value_name("PACKAGE")
+                .help("Switches the specified package back to a previous version, then exits")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("rollback-to")
+                .long("rollback-to")
+                .value_name("VERSION")
+                .help("The version to switch back to (defaults to the most recent one)")
+                .takes_value(true)
+                .requires("rollback"),
+        )
         .arg(
             Arg::with_name("swap")
                 .long("swap")
@@ -146,6 +171,18 @@ fn main() {
         let _ = replace_existing_install(&current_exe, &installed_path);
     }
 
+    if let Some(package) = matches.value_of("rollback") {
+        let version = matches.value_of("rollback-to").map(|x| x.to_string());
+
+        match rollback_from_cli(&mut framework, package.to_string(), version) {
+            Ok(()) => exit(0),
+            Err(v) => {
+                error!("Rollback failed: {}", v);
+                exit(1);
+            }
+        }
+    }
+
     let is_launcher = if let Some(string) = matches.value_of("launcher") {
         framework.is_launcher = true;
         framework.launcher_path = Some(string.to_string());
@@ -158,6 +195,37 @@ fn main() {
     frontend::launch(&app_name, is_launcher, framework);
 }
 
+fn rollback_from_cli(
+    framework: &mut InstallerFramework,
+    package: String,
+    version: Option<String>,
+) -> Result<(), String> {
+    if !framework.preexisting_install {
+        return Err("No existing installation was found to roll back".to_string());
+    }
+
+    // Shortcuts are regenerated from the package descriptions, but as their paths don't
+    // change between versions, the existing ones still work if this isn't possible
+    if let Err(v) = framework.download_config() {
+        warn!(
+            "Not regenerating shortcuts, as the configuration is unavailable: {}",
+            v
+        );
+    }
+
+    let (sender, receiver) = channel();
+
+    thread::spawn(move || {
+        for message in receiver {
+            if let InstallMessage::Status(message, _) = message {
+                info!("{}", message);
+            }
+        }
+    });
+
+    framework.rollback(package, version, &sender)
+}
+
 fn replace_existing_install(current_exe: &PathBuf, installed_path: &PathBuf) -> Result<(), String> {
     // Generate installer path
     let platform_extension = if cfg!(windows) {
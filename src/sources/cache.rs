@@ -0,0 +1,92 @@
+//! cache.rs
+//!
+//! Contains a on-disk cache of release listings, so that sources don't need to be polled
+//! on every launch.
+
+use crate::sources::types::Release;
+
+use serde_json;
+
+use std::fs::{create_dir_all, File};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Directory, relative to the install path, that cached listings are stored in.
+pub const CACHE_DIR: &str = ".cache/releases";
+
+/// A cached listing of releases from a source.
+#[derive(Serialize, Deserialize)]
+pub struct CacheEntry {
+    /// When this listing was last fetched or revalidated, in seconds since the Unix epoch
+    pub fetched_at: u64,
+    /// The entity tag the source returned with this listing, for revalidation
+    pub etag: Option<String>,
+    pub releases: Vec<Release>,
+}
+
+impl CacheEntry {
+    /// Creates a entry fetched just now.
+    pub fn new(releases: Vec<Release>, etag: Option<String>) -> CacheEntry {
+        CacheEntry {
+            fetched_at: now(),
+            etag,
+            releases,
+        }
+    }
+
+    /// Returns how long ago this listing was fetched, in seconds.
+    pub fn age(&self) -> u64 {
+        now().saturating_sub(self.fetched_at)
+    }
+}
+
+/// A directory of cached release listings.
+#[derive(Clone)]
+pub struct ReleaseCache {
+    path: PathBuf,
+}
+
+impl ReleaseCache {
+    /// Returns the cache for a installation.
+    pub fn new(install_path: &Path) -> ReleaseCache {
+        ReleaseCache {
+            path: install_path.join(CACHE_DIR),
+        }
+    }
+
+    /// Loads a cached listing, if one exists and is readable.
+    pub fn load(&self, key: &str) -> Option<CacheEntry> {
+        let path = self.path.join(format!("{}.json", key));
+        let file = File::open(&path).ok()?;
+
+        match serde_json::from_reader(file) {
+            Ok(v) => Some(v),
+            Err(v) => {
+                warn!("Ignoring unreadable release cache {:?}: {:?}", path, v);
+                None
+            }
+        }
+    }
+
+    /// Stores a listing. Failures are only logged, as the cache is just an optimisation.
+    pub fn store(&self, key: &str, entry: &CacheEntry) {
+        let path = self.path.join(format!("{}.json", key));
+
+        let result = create_dir_all(&self.path)
+            .map_err(|x| format!("Unable to create dir: {:?}", x))
+            .and_then(|_| File::create(&path).map_err(|x| format!("{:?}", x)))
+            .and_then(|file| serde_json::to_writer(file, entry).map_err(|x| format!("{:?}", x)));
+
+        if let Err(v) = result {
+            warn!("Unable to write release cache {:?}: {}", path, v);
+        }
+    }
+}
+
+/// Returns the current time in seconds since the Unix epoch.
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_secs())
+        .unwrap_or(0)
+}
@@ -2,14 +2,32 @@
 //!
 //! Contains the Github API implementation of a release source.
 
-use reqwest::header::USER_AGENT;
+use reqwest::header::{AUTHORIZATION, ETAG, IF_NONE_MATCH, LINK, USER_AGENT};
+use reqwest::Client;
+use reqwest::Response;
 use reqwest::StatusCode;
 
+use chrono::Local;
+
 use serde_json;
 
+use crate::sources::attach_checksums;
+use crate::sources::parse_config;
+use crate::sources::read_token;
+use crate::sources::same_origin;
 use crate::sources::types::*;
 
 use crate::http::build_client;
+use crate::http::rewrite_url;
+
+use crate::logging::LoggingErrors;
+
+use std::env::var;
+use std::thread::sleep;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The longest time, in seconds, to wait for a rate limit to reset before giving up.
+const MAX_RATE_LIMIT_WAIT: u64 = 10;
 
 pub struct GithubReleases {}
 
@@ -17,6 +35,35 @@ pub struct GithubReleases {}
 #[derive(Serialize, Deserialize)]
 struct GithubConfig {
     repo: String,
+    /// Base URL of the API, for GitHub Enterprise instances.
+    #[serde(default = "default_api_url")]
+    api_url: String,
+    /// The maximum number of pages of releases to fetch.
+    #[serde(default = "default_max_pages")]
+    max_pages: usize,
+    #[serde(default = "default_true")]
+    include_prereleases: bool,
+    /// Drafts are only visible when authenticated as a collaborator.
+    #[serde(default)]
+    include_drafts: bool,
+    /// Access token, normally provided from the installation database.
+    #[serde(default)]
+    token: Option<String>,
+    /// Name of a environment variable holding a access token. GITHUB_TOKEN is also checked.
+    #[serde(default)]
+    token_env: Option<String>,
+}
+
+fn default_api_url() -> String {
+    "https://api.github.com".to_string()
+}
+
+fn default_max_pages() -> usize {
+    5
+}
+
+fn default_true() -> bool {
+    true
 }
 
 impl GithubReleases {
@@ -25,54 +72,86 @@ impl GithubReleases {
     }
 }
 
+/// A page of releases from the API.
+struct Page {
+    entries: Vec<serde_json::Value>,
+    next_url: Option<String>,
+    etag: Option<String>,
+}
+
 impl ReleaseSource for GithubReleases {
+    fn check_config(&self, config: &TomlValue) -> Result<(), String> {
+        parse_config::<GithubConfig>(config).map(|_| ())
+    }
+
     fn get_current_releases(&self, config: &TomlValue) -> Result<Vec<Release>, String> {
-        // Reparse our Config as strongly typed
-        let config: GithubConfig = match config.clone().try_into() {
-            Ok(v) => v,
-            Err(v) => return Err(format!("Failed to parse release config: {:?}", v)),
-        };
+        match self.get_releases_if_changed(config, None)? {
+            Listing::Modified { releases, .. } => Ok(releases),
+            Listing::NotModified => Err("Releases were unexpectedly unmodified".to_string()),
+        }
+    }
+
+    fn get_releases_if_changed(
+        &self,
+        config: &TomlValue,
+        etag: Option<&str>,
+    ) -> Result<Listing, String> {
+        let config: GithubConfig = parse_config(config)?;
 
         let mut results: Vec<Release> = Vec::new();
 
+        let token = read_token(&config.token, &config.token_env)
+            .or_else(|| var("GITHUB_TOKEN").ok().filter(|x| !x.is_empty()));
+
         // Build the HTTP client up
         let client = build_client()?;
-        let mut response = client
-            .get(&format!(
-                "https://api.github.com/repos/{}/releases",
-                config.repo
-            ))
-            .header(USER_AGENT, "liftinstall (j-selby)")
-            .send()
-            .map_err(|x| format!("Error while sending HTTP request: {:?}", x))?;
 
-        match response.status() {
-            StatusCode::OK => {}
-            StatusCode::FORBIDDEN => {
-                return Err(
-                    "GitHub is rate limiting you. Try moving to a internet connection \
-                     that isn't shared, and/or disabling VPNs."
-                        .to_string(),
-                );
-            }
-            _ => {
-                return Err(format!("Bad status code: {:?}.", response.status()));
-            }
-        }
+        let first_url = format!(
+            "{}/repos/{}/releases?per_page=100",
+            config.api_url.trim_end_matches('/'),
+            config.repo
+        );
 
-        let body = response
-            .text()
-            .map_err(|x| format!("Failed to decode HTTP response body: {:?}", x))?;
+        // New releases always show up on the first page, so only it needs revalidating
+        let first_page =
+            match fetch_page(&client, &first_url, etag, &config.api_url, token.as_deref())? {
+                Some(v) => v,
+                None => return Ok(Listing::NotModified),
+            };
+
+        let new_etag = first_page.etag;
+        let mut entries = first_page.entries;
+        let mut next_url = first_page.next_url;
 
-        let result: serde_json::Value = serde_json::from_str(&body)
-            .map_err(|x| format!("Failed to parse response: {:?}", x))?;
+        for _ in 1..config.max_pages {
+            let url = match next_url.take() {
+                Some(v) => v,
+                None => break,
+            };
 
-        let result: &Vec<serde_json::Value> = result
-            .as_array()
-            .ok_or_else(|| "Response was not an array!".to_string())?;
+            let page = fetch_page(&client, &url, None, &config.api_url, token.as_deref())?
+                .log_expect("Unconditional requests should always be modified");
+            entries.extend(page.entries);
+            next_url = page.next_url;
+        }
+
+        if next_url.is_some() {
+            info!(
+                "Only checked the first {} pages of releases for {:?}",
+                config.max_pages, config.repo
+            );
+        }
 
         // Parse JSON from server
-        for entry in result.iter() {
+        for entry in entries.iter() {
+            if entry["draft"].as_bool().unwrap_or(false) && !config.include_drafts {
+                continue;
+            }
+
+            if entry["prerelease"].as_bool().unwrap_or(false) && !config.include_prereleases {
+                continue;
+            }
+
             let mut files = Vec::new();
 
             let id: u64 = match entry["id"].as_u64() {
@@ -80,6 +159,18 @@ impl ReleaseSource for GithubReleases {
                 None => return Err("JSON payload missing information about ID".to_string()),
             };
 
+            // Prefer the tag name, as release IDs aren't necessarily in release order
+            let version = match entry["tag_name"].as_str().and_then(Version::parse) {
+                Some(v) => v,
+                None => {
+                    warn!(
+                        "Unable to parse version from tag {:?}, using release ID {}",
+                        entry["tag_name"], id
+                    );
+                    Version::new_number(id)
+                }
+            };
+
             let assets = match entry["assets"].as_array() {
                 Some(v) => v,
                 None => return Err("JSON payload not an array".to_string()),
@@ -108,15 +199,181 @@ impl ReleaseSource for GithubReleases {
                     name: string.to_string(),
                     url: url.to_string(),
                     requires_authorization: false,
+                    size: asset["size"].as_u64(),
+                    checksum: None,
+                    signature_url: None,
                 });
             }
 
+            attach_checksums(&mut files);
+
             results.push(Release {
-                version: Version::new_number(id),
+                version,
                 files,
+                title: entry["name"].as_str().map(|x| x.to_string()),
+                notes: entry["body"].as_str().map(|x| x.to_string()),
+                published_at: entry["published_at"].as_str().map(|x| x.to_string()),
             });
         }
 
-        Ok(results)
+        Ok(Listing::Modified {
+            releases: results,
+            etag: new_etag,
+        })
+    }
+}
+
+/// Fetches a page of releases. If a entity tag is given and the page is unchanged, returns
+/// None. The token is only sent if the page is served from the configured API's origin.
+fn fetch_page(
+    client: &Client,
+    url: &str,
+    etag: Option<&str>,
+    api_url: &str,
+    token: Option<&str>,
+) -> Result<Option<Page>, String> {
+    let url = rewrite_url(url);
+    let token = token.filter(|_| same_origin(&url, api_url));
+
+    let mut waited = false;
+
+    let mut response = loop {
+        let mut request = client.get(&url).header(USER_AGENT, "liftinstall (j-selby)");
+
+        if let Some(etag) = etag {
+            request = request.header(IF_NONE_MATCH, etag);
+        }
+
+        if let Some(token) = token {
+            request = request.header(AUTHORIZATION, format!("Bearer {}", token));
+        }
+
+        let response = request
+            .send()
+            .map_err(|x| format!("Error while sending HTTP request: {:?}", x))?;
+
+        match response.status() {
+            StatusCode::FORBIDDEN | StatusCode::TOO_MANY_REQUESTS => {}
+            _ => break response,
+        }
+
+        let wait = match rate_limit_wait(&response) {
+            Some(v) => v,
+            None if response.status() == StatusCode::FORBIDDEN && token.is_some() => {
+                return Err("GitHub rejected the provided access token.".to_string());
+            }
+            None => {
+                return Err(
+                    "GitHub is rate limiting you. Try moving to a internet connection \
+                     that isn't shared, and/or disabling VPNs."
+                        .to_string(),
+                );
+            }
+        };
+
+        // Don't make the user retry by hand if the limit is about to reset anyway
+        if wait <= MAX_RATE_LIMIT_WAIT && !waited {
+            info!("GitHub rate limit resets in {}s, waiting", wait);
+            sleep(Duration::from_secs(wait + 1));
+            waited = true;
+            continue;
+        }
+
+        let retry_at = Local::now() + chrono::Duration::seconds(wait as i64);
+
+        return Err(format!(
+            "GitHub's API rate limit has been reached. Try again in {} minute(s), at {}.{}",
+            wait.div_ceil(60),
+            retry_at.format("%H:%M"),
+            if token.is_some() {
+                ""
+            } else {
+                " Setting a access token in the GITHUB_TOKEN environment variable \
+                 raises this limit."
+            }
+        ));
+    };
+
+    match response.status() {
+        StatusCode::OK => {}
+        StatusCode::NOT_MODIFIED if etag.is_some() => return Ok(None),
+        StatusCode::UNAUTHORIZED => {
+            return Err("GitHub rejected the provided access token.".to_string());
+        }
+        _ => {
+            return Err(format!("Bad status code: {:?}.", response.status()));
+        }
     }
+
+    let next_url = response
+        .headers()
+        .get(LINK)
+        .and_then(|x| x.to_str().ok())
+        .and_then(parse_next_link);
+
+    let etag = response
+        .headers()
+        .get(ETAG)
+        .and_then(|x| x.to_str().ok())
+        .map(|x| x.to_string());
+
+    let body = response
+        .text()
+        .map_err(|x| format!("Failed to decode HTTP response body: {:?}", x))?;
+
+    let result: serde_json::Value =
+        serde_json::from_str(&body).map_err(|x| format!("Failed to parse response: {:?}", x))?;
+
+    match result {
+        serde_json::Value::Array(entries) => Ok(Some(Page {
+            entries,
+            next_url,
+            etag,
+        })),
+        _ => Err("Response was not an array!".to_string()),
+    }
+}
+
+/// Finds the URL of the next page in a `Link` header, such as
+/// `<https://api.github.com/...&page=2>; rel="next", <...>; rel="last"`.
+fn parse_next_link(header: &str) -> Option<String> {
+    header
+        .split(',')
+        .find(|x| x.split(';').skip(1).any(|x| x.trim() == "rel=\"next\""))
+        .and_then(|x| x.split(';').next())
+        .map(|x| {
+            x.trim()
+                .trim_start_matches('<')
+                .trim_end_matches('>')
+                .to_string()
+        })
+}
+
+/// Returns how many seconds to wait before retrying a rate limited request, if the
+/// response indicates that it was rate limited.
+fn rate_limit_wait(response: &Response) -> Option<u64> {
+    let header = |name: &str| -> Option<u64> {
+        response
+            .headers()
+            .get(name)
+            .and_then(|x| x.to_str().ok())
+            .and_then(|x| x.trim().parse().ok())
+    };
+
+    // Secondary rate limits say how long to wait directly
+    if let Some(seconds) = header("retry-after") {
+        return Some(seconds);
+    }
+
+    if header("x-ratelimit-remaining")? != 0 {
+        return None;
+    }
+
+    let reset = header("x-ratelimit-reset")?;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_secs())
+        .unwrap_or(0);
+
+    Some(reset.saturating_sub(now))
 }
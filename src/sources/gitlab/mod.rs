@@ -0,0 +1,204 @@
+//! gitlab/mod.rs
+//!
+//! Contains the GitLab API implementation of a release source, for gitlab.com or a
+//! self-hosted instance.
+
+use reqwest::header::USER_AGENT;
+use reqwest::StatusCode;
+
+use serde_json;
+
+use url::form_urlencoded;
+
+use crate::sources::attach_checksums;
+use crate::sources::parse_config;
+use crate::sources::read_token;
+use crate::sources::same_origin;
+use crate::sources::types::*;
+
+use crate::http::assert_ssl;
+use crate::http::build_client;
+use crate::http::rewrite_url;
+
+/// Number of releases requested per page.
+const PER_PAGE: usize = 100;
+
+pub struct GitlabReleases {}
+
+/// The configuration for this release.
+#[derive(Serialize, Deserialize)]
+struct GitlabConfig {
+    /// The project's path (e.g. "group/project") or numeric ID.
+    project: String,
+    #[serde(default = "default_base_url")]
+    base_url: String,
+    /// Access token, normally provided from the installation database.
+    #[serde(default)]
+    token: Option<String>,
+    /// Name of a environment variable holding a access token, for private projects.
+    #[serde(default)]
+    token_env: Option<String>,
+    #[serde(default = "default_max_pages")]
+    max_pages: usize,
+}
+
+fn default_base_url() -> String {
+    "https://gitlab.com".to_string()
+}
+
+fn default_max_pages() -> usize {
+    5
+}
+
+impl GitlabReleases {
+    pub fn new() -> Self {
+        GitlabReleases {}
+    }
+}
+
+impl ReleaseSource for GitlabReleases {
+    fn check_config(&self, config: &TomlValue) -> Result<(), String> {
+        let config: GitlabConfig = parse_config(config)?;
+
+        assert_ssl(&config.base_url)
+    }
+
+    fn get_current_releases(&self, config: &TomlValue) -> Result<Vec<Release>, String> {
+        let config: GitlabConfig = parse_config(config)?;
+
+        assert_ssl(&config.base_url)?;
+
+        let token = read_token(&config.token, &config.token_env);
+        let project: String = form_urlencoded::byte_serialize(config.project.as_bytes()).collect();
+
+        let mut results: Vec<Release> = Vec::new();
+
+        // Build the HTTP client up
+        let client = build_client()?;
+
+        for page in 1..=config.max_pages {
+            let mut request = client
+                .get(&rewrite_url(&format!(
+                    "{}/api/v4/projects/{}/releases?per_page={}&page={}",
+                    config.base_url.trim_end_matches('/'),
+                    project,
+                    PER_PAGE,
+                    page
+                )))
+                .header(USER_AGENT, "liftinstall (j-selby)");
+
+            if let Some(ref token) = token {
+                request = request.header("PRIVATE-TOKEN", token.as_str());
+            }
+
+            let mut response = request
+                .send()
+                .map_err(|x| format!("Error while sending HTTP request: {:?}", x))?;
+
+            match response.status() {
+                StatusCode::OK => {}
+                StatusCode::UNAUTHORIZED | StatusCode::NOT_FOUND => {
+                    return Err(format!(
+                        "GitLab project {:?} was not found. If it is private, make sure \
+                         a access token is available.",
+                        config.project
+                    ));
+                }
+                _ => {
+                    return Err(format!("Bad status code: {:?}.", response.status()));
+                }
+            }
+
+            let body = response
+                .text()
+                .map_err(|x| format!("Failed to decode HTTP response body: {:?}", x))?;
+
+            let result: serde_json::Value = serde_json::from_str(&body)
+                .map_err(|x| format!("Failed to parse response: {:?}", x))?;
+
+            let result: &Vec<serde_json::Value> = result
+                .as_array()
+                .ok_or_else(|| "Response was not an array!".to_string())?;
+
+            // Parse JSON from server
+            for entry in result.iter() {
+                // Releases scheduled for the future aren't available yet
+                if entry["upcoming_release"].as_bool().unwrap_or(false) {
+                    continue;
+                }
+
+                let version = match entry["tag_name"].as_str().and_then(Version::parse) {
+                    Some(v) => v,
+                    None => return Err("JSON payload missing information about tag".to_string()),
+                };
+
+                let links = match entry["assets"]["links"].as_array() {
+                    Some(v) => v,
+                    None => return Err("JSON payload not an array".to_string()),
+                };
+
+                let mut files = Vec::new();
+
+                for link in links.iter() {
+                    let name = match link["name"].as_str() {
+                        Some(v) => v,
+                        None => {
+                            return Err(
+                                "JSON payload missing information about release name".to_string()
+                            );
+                        }
+                    };
+
+                    // Prefer the permanent link, as the target may change
+                    let url = match link["direct_asset_url"]
+                        .as_str()
+                        .or_else(|| link["url"].as_str())
+                    {
+                        Some(v) => v,
+                        None => {
+                            return Err(
+                                "JSON payload missing information about release URL".to_string()
+                            );
+                        }
+                    };
+
+                    files.push(File {
+                        name: name.to_string(),
+                        url: url.to_string(),
+                        requires_authorization: false,
+                        size: None,
+                        checksum: None,
+                        signature_url: None,
+                    });
+                }
+
+                attach_checksums(&mut files);
+
+                results.push(Release {
+                    version,
+                    files,
+                    title: entry["name"].as_str().map(|x| x.to_string()),
+                    notes: entry["description"].as_str().map(|x| x.to_string()),
+                    published_at: entry["released_at"].as_str().map(|x| x.to_string()),
+                });
+            }
+
+            if result.len() < PER_PAGE {
+                break;
+            }
+        }
+
+        Ok(results)
+    }
+
+    fn download_token(&self, config: &TomlValue, url: &str) -> Option<String> {
+        let config: GitlabConfig = parse_config(config).ok()?;
+
+        // Only send the token to the instance itself, not to externally hosted files
+        if !same_origin(&rewrite_url(url), &config.base_url) {
+            return None;
+        }
+
+        read_token(&config.token, &config.token_env)
+    }
+}
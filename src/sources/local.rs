@@ -0,0 +1,146 @@
+//! local.rs
+//!
+//! Contains a release source which scans a local directory (or `file://` URL), for offline
+//! installs from removable media or network shares. Versions are derived from file names,
+//! and files with the same version are grouped into one release.
+
+use url::Url;
+
+use crate::sources::attach_checksums;
+use crate::sources::parse_config;
+use crate::sources::types::*;
+use crate::sources::SHA256SUMS_NAMES;
+
+use crate::http::local_path;
+
+use std::fs::metadata;
+use std::fs::read_dir;
+use std::path::PathBuf;
+
+pub struct LocalReleases {}
+
+/// The configuration for this release.
+#[derive(Serialize, Deserialize)]
+struct LocalConfig {
+    /// A directory path, or a `file://` URL to one.
+    path: String,
+}
+
+impl LocalReleases {
+    pub fn new() -> Self {
+        LocalReleases {}
+    }
+}
+
+/// Strips trailing file extensions (e.g. ".tar.xz" or ".AppImage") from a file name,
+/// leaving the version intact.
+fn strip_extensions(name: &str) -> &str {
+    let mut name = name;
+
+    while let Some((stem, extension)) = name.rsplit_once('.') {
+        let is_extension = extension == "7z"
+            || (extension.len() <= 10
+                && extension.starts_with(|c: char| c.is_ascii_alphabetic())
+                && extension.chars().all(|c| c.is_ascii_alphanumeric()));
+
+        if stem.is_empty() || !is_extension {
+            break;
+        }
+
+        name = stem;
+    }
+
+    name
+}
+
+impl ReleaseSource for LocalReleases {
+    fn check_config(&self, config: &TomlValue) -> Result<(), String> {
+        let config: LocalConfig = parse_config(config)?;
+
+        if config.path.starts_with("file://") && local_path(&config.path).is_none() {
+            return Err(format!("Invalid file URL: {:?}", config.path));
+        }
+
+        Ok(())
+    }
+
+    fn get_current_releases(&self, config: &TomlValue) -> Result<Vec<Release>, String> {
+        let config: LocalConfig = parse_config(config)?;
+
+        let path = if config.path.starts_with("file://") {
+            local_path(&config.path)
+                .ok_or_else(|| format!("Invalid file URL: {:?}", config.path))?
+        } else {
+            PathBuf::from(&config.path)
+        };
+
+        let entries = read_dir(&path).map_err(|x| format!("Unable to read {:?}: {:?}", path, x))?;
+
+        let mut results: Vec<Release> = Vec::new();
+        let mut listings = Vec::new();
+
+        for entry in entries {
+            let entry = entry.map_err(|x| format!("Unable to read {:?}: {:?}", path, x))?;
+
+            // Follow symlinks, as builds are often linked into place
+            if !entry.path().is_file() {
+                continue;
+            }
+
+            let name = match entry.file_name().into_string() {
+                Ok(v) => v,
+                Err(v) => {
+                    warn!("Skipping file with non UTF-8 name: {:?}", v);
+                    continue;
+                }
+            };
+
+            let absolute = entry
+                .path()
+                .canonicalize()
+                .map_err(|x| format!("Unable to resolve {:?}: {:?}", entry.path(), x))?;
+
+            let url = Url::from_file_path(&absolute)
+                .map_err(|_| format!("Unable to build URL for {:?}", absolute))?
+                .to_string();
+
+            let file = File {
+                size: metadata(&absolute).ok().map(|x| x.len()),
+                name,
+                url,
+                requires_authorization: false,
+                checksum: None,
+                signature_url: None,
+            };
+
+            // A checksum listing describes every file in the directory
+            if SHA256SUMS_NAMES.contains(&file.name.as_str()) {
+                listings.push(file);
+                continue;
+            }
+
+            let version = match Version::parse(strip_extensions(&file.name)) {
+                Some(v) => v,
+                None => continue,
+            };
+
+            match results.iter_mut().find(|x| x.version == version) {
+                Some(release) => release.files.push(file),
+                None => results.push(Release {
+                    version,
+                    files: vec![file],
+                    title: None,
+                    notes: None,
+                    published_at: None,
+                }),
+            }
+        }
+
+        for release in results.iter_mut() {
+            release.files.extend(listings.iter().cloned());
+            attach_checksums(&mut release.files);
+        }
+
+        Ok(results)
+    }
+}
@@ -0,0 +1,298 @@
+//! manifest.rs
+//!
+//! Contains a release source backed by a manifest file hosted on any HTTPS server, so that
+//! packages can be distributed from a plain web server or CDN.
+//!
+//! The manifest is JSON (or TOML, if the URL ends in `.toml` or `format = "toml"` is
+//! specified) in the following format. Only `version`, `name` and `url` are required, and
+//! file URLs may be relative to the manifest itself:
+//!
+//! ```json
+//! {
+//!   "releases": [
+//!     {
+//!       "version": "1.2.3",
+//!       "title": "Version 1.2.3",
+//!       "notes": "Fixed a bug.",
+//!       "published_at": "2023-01-01T00:00:00Z",
+//!       "files": [
+//!         {
+//!           "name": "app-linux-1.2.3.tar.xz",
+//!           "url": "app-linux-1.2.3.tar.xz",
+//!           "size": 12345678,
+//!           "sha256": "<hex digest>",
+//!           "signature_url": "app-linux-1.2.3.tar.xz.minisig",
+//!           "requires_authorization": false
+//!         }
+//!       ]
+//!     }
+//!   ]
+//! }
+//! ```
+//!
+//! Versions may either be strings (parsed in the same way as tag names) or integers.
+
+use reqwest::header::USER_AGENT;
+use reqwest::StatusCode;
+
+use url::Url;
+
+use crate::sources::parse_config;
+use crate::sources::types::*;
+
+use crate::http::assert_ssl;
+use crate::http::build_client;
+use crate::http::rewrite_url;
+
+pub struct ManifestReleases {}
+
+/// The configuration for this release.
+#[derive(Serialize, Deserialize)]
+struct ManifestConfig {
+    url: String,
+    /// Either "json" or "toml". Detected from the URL if not specified.
+    #[serde(default)]
+    format: Option<String>,
+}
+
+/// The root of a manifest file.
+#[derive(Deserialize)]
+struct Manifest {
+    releases: Vec<ManifestRelease>,
+}
+
+/// A version, as written in a manifest file.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ManifestVersion {
+    Number(u64),
+    Text(String),
+}
+
+/// A release, as written in a manifest file.
+#[derive(Deserialize)]
+struct ManifestRelease {
+    version: ManifestVersion,
+    #[serde(default)]
+    title: Option<String>,
+    #[serde(default)]
+    notes: Option<String>,
+    #[serde(default)]
+    published_at: Option<String>,
+    #[serde(default)]
+    files: Vec<ManifestFile>,
+}
+
+/// A file, as written in a manifest file.
+#[derive(Deserialize)]
+struct ManifestFile {
+    name: String,
+    url: String,
+    #[serde(default)]
+    size: Option<u64>,
+    #[serde(default)]
+    sha256: Option<String>,
+    #[serde(default)]
+    signature_url: Option<String>,
+    #[serde(default)]
+    requires_authorization: bool,
+}
+
+impl ManifestReleases {
+    pub fn new() -> Self {
+        ManifestReleases {}
+    }
+}
+
+impl ReleaseSource for ManifestReleases {
+    fn check_config(&self, config: &TomlValue) -> Result<(), String> {
+        let config: ManifestConfig = parse_config(config)?;
+
+        Url::parse(&config.url).map_err(|x| format!("Invalid manifest URL: {:?}", x))?;
+
+        match config.format.as_deref() {
+            Some("toml") | Some("json") | None => Ok(()),
+            Some(v) => Err(format!("Unknown manifest format: {:?}", v)),
+        }
+    }
+
+    fn get_current_releases(&self, config: &TomlValue) -> Result<Vec<Release>, String> {
+        let config: ManifestConfig = parse_config(config)?;
+
+        let base_url =
+            Url::parse(&config.url).map_err(|x| format!("Invalid manifest URL: {:?}", x))?;
+
+        let url = rewrite_url(&config.url);
+        assert_ssl(&url)?;
+
+        // Build the HTTP client up
+        let client = build_client()?;
+        let mut response = client
+            .get(&url)
+            .header(USER_AGENT, "liftinstall (j-selby)")
+            .send()
+            .map_err(|x| format!("Error while sending HTTP request: {:?}", x))?;
+
+        match response.status() {
+            StatusCode::OK => {}
+            _ => {
+                return Err(format!("Bad status code: {:?}.", response.status()));
+            }
+        }
+
+        let body = response
+            .text()
+            .map_err(|x| format!("Failed to decode HTTP response body: {:?}", x))?;
+
+        parse_manifest(&body, &base_url, config.format.as_deref())
+    }
+}
+
+/// Parses the releases listed in a manifest, resolving file URLs relative to the manifest's
+/// own URL. URL rewrite rules are applied when files are downloaded, not here.
+fn parse_manifest(
+    body: &str,
+    base_url: &Url,
+    format: Option<&str>,
+) -> Result<Vec<Release>, String> {
+    let is_toml = match format {
+        Some("toml") => true,
+        Some("json") => false,
+        Some(v) => return Err(format!("Unknown manifest format: {:?}", v)),
+        None => base_url.path().ends_with(".toml"),
+    };
+
+    let manifest: Manifest = if is_toml {
+        toml::from_str(body).map_err(|x| format!("Failed to parse manifest: {:?}", x))?
+    } else {
+        serde_json::from_str(body).map_err(|x| format!("Failed to parse manifest: {:?}", x))?
+    };
+
+    let resolve = |url: &str| -> Result<String, String> {
+        Ok(base_url
+            .join(url)
+            .map_err(|x| format!("Invalid URL {:?} in manifest: {:?}", url, x))?
+            .to_string())
+    };
+
+    let mut results: Vec<Release> = Vec::new();
+
+    for release in manifest.releases {
+        let version = match release.version {
+            ManifestVersion::Number(v) => Version::new_number(v),
+            ManifestVersion::Text(v) => {
+                Version::parse(&v).ok_or_else(|| "Manifest contains a empty version".to_string())?
+            }
+        };
+
+        let mut files = Vec::new();
+
+        for file in release.files {
+            files.push(File {
+                url: resolve(&file.url)?,
+                name: file.name,
+                requires_authorization: file.requires_authorization,
+                size: file.size,
+                checksum: file.sha256.map(Checksum::Sha256),
+                signature_url: match file.signature_url {
+                    Some(v) => Some(resolve(&v)?),
+                    None => None,
+                },
+            });
+        }
+
+        results.push(Release {
+            version,
+            files,
+            title: release.title,
+            notes: release.notes,
+            published_at: release.published_at,
+        });
+    }
+
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const JSON: &str = r#"{
+        "releases": [
+            {
+                "version": "1.2.3",
+                "title": "Version 1.2.3",
+                "files": [
+                    {
+                        "name": "app-1.2.3.tar.xz",
+                        "url": "files/app-1.2.3.tar.xz",
+                        "size": 1234,
+                        "sha256": "abcdef",
+                        "signature_url": "files/app-1.2.3.tar.xz.minisig"
+                    }
+                ]
+            },
+            {
+                "version": 1000,
+                "files": [
+                    {"name": "app-1000.zip", "url": "https://cdn.example.com/app-1000.zip"}
+                ]
+            }
+        ]
+    }"#;
+
+    const TOML: &str = r#"
+        [[releases]]
+        version = "2.0.0"
+
+        [[releases.files]]
+        name = "app-2.0.0.tar.xz"
+        url = "app-2.0.0.tar.xz"
+    "#;
+
+    fn url(url: &str) -> Url {
+        Url::parse(url).unwrap()
+    }
+
+    #[test]
+    fn parses_json_manifests() {
+        let releases =
+            parse_manifest(JSON, &url("https://example.com/app/manifest.json"), None).unwrap();
+
+        assert_eq!(releases.len(), 2);
+        assert_eq!(releases[0].version, Version::parse("1.2.3").unwrap());
+        assert_eq!(releases[0].title.as_deref(), Some("Version 1.2.3"));
+        assert_eq!(releases[1].version, Version::new_number(1000));
+
+        let file = &releases[0].files[0];
+        assert_eq!(file.url, "https://example.com/app/files/app-1.2.3.tar.xz");
+        assert_eq!(file.size, Some(1234));
+        assert_eq!(
+            file.signature_url.as_deref(),
+            Some("https://example.com/app/files/app-1.2.3.tar.xz.minisig")
+        );
+        assert!(matches!(&file.checksum, Some(Checksum::Sha256(x)) if x == "abcdef"));
+
+        // Absolute URLs are left alone
+        let file = &releases[1].files[0];
+        assert_eq!(file.url, "https://cdn.example.com/app-1000.zip");
+        assert!(file.checksum.is_none());
+    }
+
+    #[test]
+    fn selects_format() {
+        let releases =
+            parse_manifest(TOML, &url("https://example.com/manifest.toml"), None).unwrap();
+        assert_eq!(releases[0].version, Version::parse("2.0.0").unwrap());
+        assert_eq!(
+            releases[0].files[0].url,
+            "https://example.com/app-2.0.0.tar.xz"
+        );
+
+        // An explicit format overrides the URL
+        assert!(parse_manifest(TOML, &url("https://example.com/manifest"), Some("toml")).is_ok());
+        assert!(parse_manifest(JSON, &url("https://example.com/a.toml"), Some("json")).is_ok());
+        assert!(parse_manifest(TOML, &url("https://example.com/manifest"), None).is_err());
+        assert!(parse_manifest(JSON, &url("https://example.com/manifest"), Some("xml")).is_err());
+    }
+}
@@ -4,17 +4,99 @@
 
 pub mod types;
 
+pub mod cache;
+
+pub mod gitea;
+
 pub mod github;
 
+pub mod gitlab;
+
+pub mod local;
+
+pub mod manifest;
+
 pub mod patreon;
 
-use self::types::ReleaseSource;
+use self::types::{Checksum, File, ReleaseSource, TomlValue};
+
+use serde::de::DeserializeOwned;
+
+use std::env::var;
+
+use url::Url;
+
+/// Names of release assets which list the SHA-256 digests of other assets.
+pub const SHA256SUMS_NAMES: &[&str] = &["SHA256SUMS", "SHA256SUMS.txt", "sha256sums.txt"];
 
 /// Returns a ReleaseSource by a name, if possible
 pub fn get_by_name(name: &str) -> Option<Box<dyn ReleaseSource>> {
     match name {
+        "gitea" => Some(Box::new(gitea::GiteaReleases::new())),
         "github" => Some(Box::new(github::GithubReleases::new())),
+        "gitlab" => Some(Box::new(gitlab::GitlabReleases::new())),
+        "local" => Some(Box::new(local::LocalReleases::new())),
+        "manifest" => Some(Box::new(manifest::ManifestReleases::new())),
         "patreon" => Some(Box::new(patreon::PatreonReleases::new())),
         _ => None,
     }
 }
+
+/// Reparses a source's configuration as strongly typed.
+pub fn parse_config<T: DeserializeOwned>(config: &TomlValue) -> Result<T, String> {
+    config
+        .clone()
+        .try_into()
+        .map_err(|v| format!("Failed to parse release config: {:?}", v))
+}
+
+/// Checks if two URLs share a scheme, host and port.
+pub fn same_origin(url: &str, other: &str) -> bool {
+    match (Url::parse(url), Url::parse(other)) {
+        (Ok(url), Ok(other)) => url.origin() == other.origin(),
+        _ => false,
+    }
+}
+
+/// Attaches checksums published as other assets of the same release to each file, either
+/// from a `<name>.sha256` sidecar or a `SHA256SUMS` listing.
+pub fn attach_checksums(files: &mut [File]) {
+    let sums_url = files
+        .iter()
+        .find(|x| SHA256SUMS_NAMES.contains(&x.name.as_str()))
+        .map(|x| x.url.clone());
+
+    let sidecars: Vec<(String, String)> = files
+        .iter()
+        .filter(|x| x.name.ends_with(".sha256"))
+        .map(|x| (x.name.clone(), x.url.clone()))
+        .collect();
+
+    for file in files.iter_mut() {
+        let sidecar_name = format!("{}.sha256", file.name);
+
+        file.checksum = sidecars
+            .iter()
+            .find(|(name, _)| *name == sidecar_name)
+            .map(|(_, url)| Checksum::Sha256Url(url.clone()))
+            .or_else(|| sums_url.clone().map(Checksum::Sha256SumsUrl));
+    }
+}
+
+/// Reads a source's access token, either given directly (normally from the installation
+/// database) or from the environment variable named in the source's configuration.
+pub fn read_token(token: &Option<String>, token_env: &Option<String>) -> Option<String> {
+    if token.is_some() {
+        return token.clone();
+    }
+
+    let name = token_env.as_ref()?;
+
+    match var(name) {
+        Ok(v) if !v.is_empty() => Some(v),
+        _ => {
+            warn!("No access token found in environment variable {:?}", name);
+            None
+        }
+    }
+}
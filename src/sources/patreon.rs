@@ -3,6 +3,8 @@
 //! Contains the yuzu-emu core API implementation of a release source.
 
 use crate::http::build_client;
+use crate::http::rewrite_url;
+use crate::sources::parse_config;
 use crate::sources::types::*;
 use reqwest::header::USER_AGENT;
 use reqwest::StatusCode;
@@ -22,21 +24,22 @@ impl PatreonReleases {
 }
 
 impl ReleaseSource for PatreonReleases {
+    fn check_config(&self, config: &TomlValue) -> Result<(), String> {
+        parse_config::<PatreonConfig>(config).map(|_| ())
+    }
+
     fn get_current_releases(&self, _config: &TomlValue) -> Result<Vec<Release>, String> {
-        let config: PatreonConfig = match _config.clone().try_into() {
-            Ok(v) => v,
-            Err(v) => return Err(format!("Failed to parse release config: {:?}", v)),
-        };
+        let config: PatreonConfig = parse_config(_config)?;
 
         let mut results: Vec<Release> = Vec::new();
 
         // Build the HTTP client up
         let client = build_client()?;
         let mut response = client
-            .get(&format!(
+            .get(&rewrite_url(&format!(
                 "https://api.yuzu-emu.org/downloads/{}/",
                 config.repo
-            ))
+            )))
             .header(USER_AGENT, "liftinstall (j-selby)")
             .send()
             .map_err(|x| format!("Error while sending HTTP request: {:?}", x))?;
@@ -86,16 +89,26 @@ impl ReleaseSource for PatreonReleases {
                 }
             };
 
+            let checksum = file["sha256"]
+                .as_str()
+                .map(|x| Checksum::Sha256(x.to_string()));
+
             files.push(File {
                 name: string.to_string(),
                 url: url.to_string(),
                 requires_authorization: true,
+                size: file["size"].as_u64(),
+                checksum,
+                signature_url: None,
             });
         }
 
         results.push(Release {
             version: Version::new_number(id),
             files,
+            title: result["title"].as_str().map(|x| x.to_string()),
+            notes: result["notes"].as_str().map(|x| x.to_string()),
+            published_at: result["published_at"].as_str().map(|x| x.to_string()),
         });
         Ok(results)
     }
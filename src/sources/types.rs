@@ -3,30 +3,36 @@
 //! Contains traits that should be implemented by sources, as well as corresponding types.
 
 use std::cmp::Ordering;
+use std::fmt;
+
+use chrono::NaiveDate;
 
 pub use semver::Version as SemverVersion;
 
 pub use toml::value::Value as TomlValue;
 
 /// The version of an application.
+///
+/// Versions of different kinds are ordered by kind (opaque tags, then build numbers, then
+/// dates, then semver), so that a source which changes versioning schemes is still
+/// ordered consistently.
 #[derive(Debug, Eq, PartialEq, Clone, Serialize, Deserialize)]
 pub enum Version {
     Semver(SemverVersion),
     Integer(u64),
+    /// A date-stamped build in the form YYYYMMDD, optionally followed by a build
+    /// identifier (e.g. a commit hash) which only disambiguates builds on the same day.
+    Date {
+        date: u32,
+        build: Option<String>,
+    },
+    /// A opaque tag, which can only be compared with other tags alphabetically.
+    Tag(String),
 }
 
 // All versions are needed, even if a source is disabled at compile-time.
 #[allow(dead_code)]
 impl Version {
-    /// Coarses versions into semver versions. This will use a integer version as the major
-    /// field if required.
-    fn coarse_into_semver(&self) -> SemverVersion {
-        match *self {
-            Version::Semver(ref version) => version.to_owned(),
-            Version::Integer(ref version) => SemverVersion::new(version.to_owned(), 0u64, 0u64),
-        }
-    }
-
     /// Returns a new Version, backed by semver.
     pub fn new_semver(version: SemverVersion) -> Version {
         Version::Semver(version)
@@ -36,49 +42,320 @@ impl Version {
     pub fn new_number(version: u64) -> Version {
         Version::Integer(version)
     }
+
+    /// Parses a human readable version, such as a tag name. Semver versions (with an
+    /// optional leading "v", and possibly without a patch number as in "v1.9") are used
+    /// as-is, including as a component of a larger name (e.g. "app-v1.2.3"). Otherwise, a YYYYMMDD component is used as a date (with
+    /// anything after it as the build), then the last purely numeric component as a build
+    /// number (e.g. "mainline-0-1234" becomes 1234). Anything else is kept as a opaque tag.
+    pub fn parse(version: &str) -> Option<Version> {
+        let version = version.trim();
+        if version.is_empty() {
+            return None;
+        }
+
+        if let Some(version) = parse_semver(version) {
+            return Some(Version::Semver(version));
+        }
+
+        let components: Vec<&str> = version.split(['-', '_']).collect();
+
+        let semver = components
+            .iter()
+            .rev()
+            .find_map(|x| parse_semver(x).map(Version::Semver));
+
+        let date = components.iter().enumerate().find_map(|(index, x)| {
+            Some(Version::Date {
+                date: parse_date(x)?,
+                build: Some(components[index + 1..].join("-")).filter(|x| !x.is_empty()),
+            })
+        });
+
+        let number = || {
+            components
+                .iter()
+                .rev()
+                .filter(|x| !x.is_empty() && x.chars().all(|c| c.is_ascii_digit()))
+                .find_map(|x| x.parse().ok())
+                .map(Version::Integer)
+        };
+
+        Some(
+            semver
+                .or(date)
+                .or_else(number)
+                .unwrap_or_else(|| Version::Tag(version.to_string())),
+        )
+    }
+
+    /// Returns the position of this kind of version in the ordering between kinds.
+    fn kind_rank(&self) -> u8 {
+        match *self {
+            Version::Tag(_) => 0,
+            Version::Integer(_) => 1,
+            Version::Date { .. } => 2,
+            Version::Semver(_) => 3,
+        }
+    }
 }
 
-impl PartialOrd for Version {
-    fn partial_cmp(&self, other: &Version) -> Option<Ordering> {
+/// Parses a semantic version, with an optional "v" prefix. Missing minor and patch numbers
+/// (as in "v1.9") are taken as zero. A bare major number is only accepted with the prefix,
+/// so that plain build numbers stay integers.
+fn parse_semver(version: &str) -> Option<SemverVersion> {
+    let stripped = version.trim_start_matches(['v', 'V']);
+
+    if let Ok(version) = SemverVersion::parse(stripped) {
+        return Some(version);
+    }
+
+    // Any pre-release or build metadata follows the numbers
+    let (numbers, suffix) = stripped.split_at(stripped.find(['-', '+']).unwrap_or(stripped.len()));
+
+    let padded = match numbers.split('.').count() {
+        1 if stripped.len() != version.len() => format!("{}.0.0{}", numbers, suffix),
+        2 => format!("{}.0{}", numbers, suffix),
+        _ => return None,
+    };
+
+    SemverVersion::parse(&padded).ok()
+}
+
+/// Parses a YYYYMMDD date component, returning it as a integer if it is a valid date.
+fn parse_date(component: &str) -> Option<u32> {
+    if component.len() != 8 || !component.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+
+    NaiveDate::parse_from_str(component, "%Y%m%d").ok()?;
+
+    component.parse().ok()
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
-            Version::Semver(ref version) => match *other {
-                Version::Semver(ref other_version) => Some(version.cmp(other_version)),
-                _ => None,
-            },
-            Version::Integer(ref num) => match *other {
-                Version::Integer(ref other_num) => Some(num.cmp(other_num)),
-                _ => None,
-            },
+            Version::Semver(ref version) => write!(f, "{}", version),
+            Version::Integer(ref version) => write!(f, "{}", version),
+            Version::Date {
+                ref date,
+                build: Some(ref build),
+            } => write!(f, "{}-{}", date, build),
+            Version::Date { ref date, .. } => write!(f, "{}", date),
+            Version::Tag(ref tag) => write!(f, "{}", tag),
         }
     }
 }
 
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Version) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
 impl Ord for Version {
     fn cmp(&self, other: &Self) -> Ordering {
-        self.coarse_into_semver().cmp(&other.coarse_into_semver())
+        match (self, other) {
+            (Version::Semver(a), Version::Semver(b)) => a.cmp(b),
+            (Version::Integer(a), Version::Integer(b)) => a.cmp(b),
+            (
+                Version::Date {
+                    date: a,
+                    build: a_build,
+                },
+                Version::Date {
+                    date: b,
+                    build: b_build,
+                },
+            ) => a.cmp(b).then_with(|| a_build.cmp(b_build)),
+            (Version::Tag(a), Version::Tag(b)) => a.cmp(b),
+            _ => self.kind_rank().cmp(&other.kind_rank()),
+        }
     }
 }
 
+/// The expected SHA-256 digest of a file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Checksum {
+    /// A hex-encoded digest.
+    Sha256(String),
+    /// URL to a `<name>.sha256` sidecar file containing the digest.
+    Sha256Url(String),
+    /// URL to a `SHA256SUMS` listing of the digests of several files.
+    Sha256SumsUrl(String),
+}
+
 /// A individual file in a release.
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct File {
     pub name: String,
     pub url: String,
     pub requires_authorization: bool,
+    /// Size of the file in bytes, if known.
+    pub size: Option<u64>,
+    pub checksum: Option<Checksum>,
+    /// URL to a detached minisign signature of this file, if one was published with it.
+    pub signature_url: Option<String>,
 }
 
 impl File {}
 
 /// A individual release of an application.
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Release {
     pub version: Version,
     pub files: Vec<File>,
+    /// Human readable name of the release
+    pub title: Option<String>,
+    /// Release notes/changelog, usually in Markdown
+    pub notes: Option<String>,
+    /// When this release was published, as a RFC 3339 timestamp
+    pub published_at: Option<String>,
+}
+
+/// The result of conditionally fetching releases from a source.
+pub enum Listing {
+    /// The releases have changed (or couldn't be revalidated), along with a entity tag to
+    /// revalidate them with next time.
+    Modified {
+        releases: Vec<Release>,
+        etag: Option<String>,
+    },
+    /// The releases are unchanged since the given entity tag.
+    NotModified,
 }
 
 /// A source of releases.
 pub trait ReleaseSource {
+    /// Checks that a configuration for this source is valid, without contacting the
+    /// source.
+    fn check_config(&self, config: &TomlValue) -> Result<(), String>;
+
     /// Gets a list of the available releases from this source. Should cache internally
     /// if possible using a mutex.
     fn get_current_releases(&self, config: &TomlValue) -> Result<Vec<Release>, String>;
+
+    /// Gets a list of the available releases, unless they are unchanged since a previous
+    /// listing with the given entity tag. Sources which can't revalidate always return
+    /// the full listing.
+    fn get_releases_if_changed(
+        &self,
+        config: &TomlValue,
+        _etag: Option<&str>,
+    ) -> Result<Listing, String> {
+        Ok(Listing::Modified {
+            releases: self.get_current_releases(config)?,
+            etag: None,
+        })
+    }
+
+    /// Returns the access token to send as a bearer token when downloading a file of a
+    /// release, if the file is served by this source and needs one.
+    fn download_token(&self, _config: &TomlValue, _url: &str) -> Option<String> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn semver(version: &str) -> Version {
+        Version::Semver(SemverVersion::parse(version).unwrap())
+    }
+
+    #[test]
+    fn parses_versions() {
+        assert_eq!(Version::parse("v1.2.3"), Some(semver("1.2.3")));
+        assert_eq!(Version::parse("app-v1.2.3"), Some(semver("1.2.3")));
+        assert_eq!(
+            Version::parse("mainline-0-1234"),
+            Some(Version::Integer(1234))
+        );
+        assert_eq!(
+            Version::parse("yuzu-linux-20230101-abc123"),
+            Some(Version::Date {
+                date: 20230101,
+                build: Some("abc123".to_string()),
+            })
+        );
+        assert_eq!(
+            Version::parse("nightly"),
+            Some(Version::Tag("nightly".to_string()))
+        );
+        assert_eq!(Version::parse(""), None);
+    }
+
+    #[test]
+    fn parses_short_semver() {
+        assert_eq!(Version::parse("v1.9"), Some(semver("1.9.0")));
+        assert_eq!(Version::parse("v2"), Some(semver("2.0.0")));
+        assert!(Version::parse("v1.9") < Version::parse("v1.10"));
+
+        // Without the prefix, a lone number is still a build number
+        assert_eq!(Version::parse("1234"), Some(Version::Integer(1234)));
+    }
+
+    #[test]
+    fn orders_same_kind_naturally() {
+        assert!(semver("1.9.0") < semver("1.10.0"));
+        assert!(Version::Integer(99) < Version::Integer(100));
+        assert!(
+            Version::Date {
+                date: 20230101,
+                build: Some("b".to_string()),
+            } < Version::Date {
+                date: 20230102,
+                build: Some("a".to_string()),
+            }
+        );
+        assert!(
+            Version::Date {
+                date: 20230101,
+                build: None,
+            } < Version::Date {
+                date: 20230101,
+                build: Some("a".to_string()),
+            }
+        );
+        assert!(Version::Tag("a".to_string()) < Version::Tag("b".to_string()));
+    }
+
+    #[test]
+    fn orders_mixed_kinds() {
+        let mut versions = vec![
+            semver("0.1.0"),
+            Version::Date {
+                date: 20230101,
+                build: None,
+            },
+            Version::Integer(1234),
+            Version::Tag("zzz".to_string()),
+        ];
+        versions.sort();
+
+        assert_eq!(
+            versions,
+            vec![
+                Version::Tag("zzz".to_string()),
+                Version::Integer(1234),
+                Version::Date {
+                    date: 20230101,
+                    build: None,
+                },
+                semver("0.1.0"),
+            ]
+        );
+
+        // The ordering is total, so the comparison agrees in both directions
+        assert_eq!(
+            Version::Integer(u64::MAX).cmp(&semver("0.0.1")),
+            Ordering::Less
+        );
+        assert_eq!(
+            semver("0.0.1").cmp(&Version::Integer(u64::MAX)),
+            Ordering::Greater
+        );
+    }
 }
@@ -11,6 +11,7 @@ use crate::tasks::{Task, TaskDependency, TaskMessage, TaskOrdering, TaskParamTyp
 
 pub struct CheckAuthorizationTask {
     pub name: String,
+    pub version: Option<String>,
 }
 
 impl Task for CheckAuthorizationTask {
@@ -25,13 +26,14 @@ impl Task for CheckAuthorizationTask {
         let params = input
             .pop()
             .log_expect("Check Authorization Task should have input from resolver!");
-        let (version, file) = match params {
-            TaskParamType::File(v, f) => Ok((v, f)),
+        let (version, file, source) = match params {
+            TaskParamType::File(v, f, s) => Ok((v, f, s)),
+            TaskParamType::Break => return Ok(TaskParamType::Break),
             _ => Err("Unexpected TaskParamType in CheckAuthorization: {:?}"),
         }?;
 
         if !file.requires_authorization {
-            return Ok(TaskParamType::Authentication(version, file, None));
+            return Ok(TaskParamType::Authentication(version, file, source, None));
         }
 
         let username = context.database.credentials.username.clone();
@@ -50,25 +52,26 @@ impl Task for CheckAuthorizationTask {
         // Authorizaion is required for this package so post the username and token and get a jwt_token response
         let jwt_token = match authentication::authenticate_sync(auth_url, username, token) {
             Ok(jwt) => jwt,
-            Err(_) => return Ok(TaskParamType::Authentication(version, file, None)),
+            Err(_) => return Ok(TaskParamType::Authentication(version, file, source, None)),
         };
 
         let claims =
             match authentication::validate_token(jwt_token.clone(), pub_key_base64, validation) {
                 Ok(c) => c,
-                Err(_) => return Ok(TaskParamType::Authentication(version, file, None)),
+                Err(_) => return Ok(TaskParamType::Authentication(version, file, source, None)),
             };
 
         // Validate that they are authorized
         if !claims.roles.contains(&"vip".to_string())
             && !claims.channels.contains(&"early-access".to_string())
         {
-            return Ok(TaskParamType::Authentication(version, file, None));
+            return Ok(TaskParamType::Authentication(version, file, source, None));
         }
 
         Ok(TaskParamType::Authentication(
             version,
             file,
+            source,
             Some(jwt_token),
         ))
     }
@@ -78,6 +81,7 @@ impl Task for CheckAuthorizationTask {
             TaskOrdering::Pre,
             Box::new(ResolvePackageTask {
                 name: self.name.clone(),
+                version: self.version.clone(),
             }),
         )]
     }
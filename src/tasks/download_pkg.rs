@@ -1,4 +1,4 @@
-//! Downloads a package into memory.
+//! Downloads a package into a temporary file within the installation directory.
 
 use crate::installer::InstallerFramework;
 
@@ -9,14 +9,31 @@ use crate::tasks::TaskMessage;
 use crate::tasks::TaskOrdering;
 use crate::tasks::TaskParamType;
 
-use crate::http::stream_file;
+use crate::http::{stream_file, stream_file_resumable};
+
+use crate::sources::types::{Checksum, File};
 
 use number_prefix::NumberPrefix::{self, Prefixed, Standalone};
 
+use minisign_verify::{PublicKey, Signature};
+
+use sha2::{Digest, Sha256};
+
+use std::cell::Cell;
+use std::fs::read_to_string;
+use std::fs::remove_file;
+use std::fs::write;
+use std::fs::OpenOptions;
+use std::io::copy;
+use std::io::Read;
+use std::io::Write;
+use std::path::Path;
+
 use crate::logging::LoggingErrors;
 
 pub struct DownloadPackageTask {
     pub name: String,
+    pub version: Option<String>,
 }
 
 impl Task for DownloadPackageTask {
@@ -31,8 +48,9 @@ impl Task for DownloadPackageTask {
         let file = input
             .pop()
             .log_expect("Download Package Task should have input from resolver!");
-        let (version, file, auth) = match file {
-            TaskParamType::Authentication(v, f, auth) => (v, f, auth),
+        let (version, file, source, auth) = match file {
+            TaskParamType::Authentication(v, f, s, auth) => (v, f, s, auth),
+            TaskParamType::Break => return Ok(TaskParamType::Break),
             _ => return Err("Unexpected param type to download package".to_string()),
         };
 
@@ -44,9 +62,13 @@ impl Task for DownloadPackageTask {
         }
 
         // Check to see if this is the newest file available already
-        for element in &context.database.packages {
+        for element in &mut context.database.packages {
             if element.name == self.name {
                 if element.version == version {
+                    if let Some(requested) = &self.version {
+                        element.pinned = requested != "latest";
+                    }
+
                     info!("{:?} is already up to date.", self.name);
                     return Ok(TaskParamType::Break);
                 }
@@ -59,42 +81,191 @@ impl Task for DownloadPackageTask {
             0.0,
         ));
 
-        let mut downloaded = 0;
-        let mut data_storage: Vec<u8> = Vec::new();
+        let path = context
+            .install_path
+            .as_ref()
+            .log_expect("No install path specified");
 
-        stream_file(&file.url, auth, |data, size| {
-            {
-                data_storage.extend_from_slice(&data);
-            }
+        // File names come from the source, so don't trust them with choosing a path
+        let download_path = path.join(format!(".{}.download", self.name));
 
-            downloaded += data.len();
+        // Files of private projects need the source's access token to be downloaded
+        let options = context.fetch_options();
+        let asset_token = |url: &str| source.download_token(url, &options);
 
-            let percentage = if size == 0 {
-                0.0
-            } else {
-                (downloaded as f64) / (size as f64)
-            };
+        let expected_checksum = match &file.checksum {
+            Some(checksum) => Some(fetch_sha256(checksum, &file.name, &asset_token)?),
+            None => None,
+        };
 
-            // Pretty print data volumes
-            let pretty_current = match NumberPrefix::decimal(downloaded as f64) {
-                Standalone(bytes) => format!("{} bytes", bytes),
-                Prefixed(prefix, n) => format!("{:.0} {}B", n, prefix),
-            };
-            let pretty_total = match NumberPrefix::decimal(size as f64) {
-                Standalone(bytes) => format!("{} bytes", bytes),
-                Prefixed(prefix, n) => format!("{:.0} {}B", n, prefix),
+        // Identifies the version of the file that the partial download is of
+        let validator_path = path.join(format!(".{}.download.validator", self.name));
+        let validator = read_to_string(&validator_path).ok();
+
+        // Pick up where any previous, interrupted attempt left off
+        let mut data_storage = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&download_path)
+            .map_err(|x| format!("Unable to open temporary download file: {:?}", x))?;
+
+        let existing = data_storage
+            .metadata()
+            .map_err(|x| format!("Unable to read temporary download file: {:?}", x))?
+            .len();
+
+        if existing > 0 {
+            info!(
+                "Found {} bytes of a previous download at {:?}",
+                existing, download_path
+            );
+        }
+
+        let truncate_handle = data_storage
+            .try_clone()
+            .map_err(|x| format!("Unable to open temporary download file: {:?}", x))?;
+
+        let downloaded = Cell::new(existing);
+
+        let start = |resumed: bool, validator: Option<&str>| {
+            if !resumed {
+                downloaded.set(0);
+                truncate_handle
+                    .set_len(0)
+                    .map_err(|x| format!("Unable to truncate temporary download file: {:?}", x))?;
+            }
+
+            // Without a validator, a partial download can't be resumed safely later
+            let result = match validator {
+                Some(validator) => write(&validator_path, validator),
+                None if validator_path.exists() => remove_file(&validator_path),
+                None => Ok(()),
             };
 
+            result.map_err(|x| format!("Unable to store download validator: {:?}", x))
+        };
+
+        stream_file_resumable(
+            &file.url,
+            auth.or_else(|| asset_token(&file.url)),
+            existing,
+            validator.as_deref(),
+            start,
+            |data, size| {
+                data_storage
+                    .write_all(&data)
+                    .map_err(|x| format!("Unable to write to temporary download file: {:?}", x))?;
+
+                downloaded.set(downloaded.get() + data.len() as u64);
+                let downloaded = downloaded.get();
+
+                let percentage = if size == 0 {
+                    0.0
+                } else {
+                    (downloaded as f64) / (size as f64)
+                };
+
+                // Pretty print data volumes
+                let pretty_current = match NumberPrefix::decimal(downloaded as f64) {
+                    Standalone(bytes) => format!("{} bytes", bytes),
+                    Prefixed(prefix, n) => format!("{:.0} {}B", n, prefix),
+                };
+                let pretty_total = match NumberPrefix::decimal(size as f64) {
+                    Standalone(bytes) => format!("{} bytes", bytes),
+                    Prefixed(prefix, n) => format!("{:.0} {}B", n, prefix),
+                };
+
+                messenger(&TaskMessage::DisplayMessage(
+                    &format!(
+                        "Downloading {} ({} of {})...",
+                        self.name, pretty_current, pretty_total
+                    ),
+                    percentage,
+                ));
+
+                Ok(())
+            },
+        )?;
+
+        // The download is complete, so there is nothing left to resume
+        if validator_path.exists() {
+            if let Err(v) = remove_file(&validator_path) {
+                warn!("Unable to remove download validator: {:?}", v);
+            }
+        }
+
+        if let Some(expected) = file.size {
+            if downloaded.get() != expected {
+                // Don't allow a future attempt to resume from bad data
+                if let Err(v) = remove_file(&download_path) {
+                    warn!("Unable to remove downloaded file: {:?}", v);
+                }
+
+                return Err(format!(
+                    "Size mismatch for {:?} (expected {} bytes, got {}).",
+                    file.name,
+                    expected,
+                    downloaded.get()
+                ));
+            }
+        }
+
+        if let Some(expected) = expected_checksum {
             messenger(&TaskMessage::DisplayMessage(
-                &format!(
-                    "Downloading {} ({} of {})...",
-                    self.name, pretty_current, pretty_total
-                ),
-                percentage,
+                &format!("Verifying {}...", self.name),
+                1.0,
             ));
-        })?;
 
-        Ok(TaskParamType::FileContents(version, file, data_storage))
+            let actual = hash_file(&download_path)?;
+
+            if actual != expected {
+                // Don't allow a future attempt to resume from bad data
+                if let Err(v) = remove_file(&download_path) {
+                    warn!("Unable to remove downloaded file: {:?}", v);
+                }
+
+                return Err(format!(
+                    "Checksum mismatch for {:?} (expected SHA-256 {}, got {}). \
+                     The download may be corrupted or have been tampered with.",
+                    file.name, expected, actual
+                ));
+            }
+
+            info!("Verified SHA-256 of {:?}: {}", file.name, actual);
+        }
+
+        let signing_key = context
+            .config
+            .as_ref()
+            .log_expect("Should have packages by now")
+            .packages
+            .iter()
+            .find(|x| x.name == self.name)
+            .and_then(|x| x.signing_key.clone());
+
+        if let Some(signing_key) = signing_key {
+            messenger(&TaskMessage::DisplayMessage(
+                &format!("Checking signature of {}...", self.name),
+                1.0,
+            ));
+
+            if let Err(v) = verify_signature(&download_path, &signing_key, &file, &asset_token) {
+                if let Err(v) = remove_file(&download_path) {
+                    warn!("Unable to remove downloaded file: {:?}", v);
+                }
+
+                return Err(v);
+            }
+
+            info!("Verified signature of {:?}", file.name);
+        }
+
+        Ok(TaskParamType::FileContents(
+            version,
+            file,
+            source,
+            download_path,
+        ))
     }
 
     fn dependencies(&self) -> Vec<TaskDependency> {
@@ -102,6 +273,7 @@ impl Task for DownloadPackageTask {
             TaskOrdering::Pre,
             Box::new(CheckAuthorizationTask {
                 name: self.name.clone(),
+                version: self.version.clone(),
             }),
         )]
     }
@@ -110,3 +282,128 @@ impl Task for DownloadPackageTask {
         format!("DownloadPackageTask (for {:?})", self.name)
     }
 }
+
+/// Resolves the expected, lowercase hex-encoded SHA-256 digest of the named file.
+fn fetch_sha256(
+    checksum: &Checksum,
+    name: &str,
+    token: &dyn Fn(&str) -> Option<String>,
+) -> Result<String, String> {
+    let (url, is_sidecar) = match checksum {
+        Checksum::Sha256(digest) => return Ok(digest.trim().to_lowercase()),
+        Checksum::Sha256Url(url) => (url, true),
+        Checksum::Sha256SumsUrl(url) => (url, false),
+    };
+
+    let mut data = Vec::new();
+    stream_file(url, token(url), |chunk, _| {
+        data.extend_from_slice(&chunk);
+        Ok(())
+    })?;
+
+    let listing = String::from_utf8(data)
+        .map_err(|x| format!("Checksum file for {:?} is invalid: {:?}", name, x))?;
+
+    // Either a bare digest, or "<digest>  <file name>" lines as generated by sha256sum
+    let entries: Vec<(&str, Option<&str>)> = listing
+        .lines()
+        .filter_map(|line| {
+            let mut parts = line.split_whitespace();
+            Some((parts.next()?, parts.next()))
+        })
+        .collect();
+
+    // A sidecar only describes one file, whatever path it was recorded with
+    if is_sidecar && entries.len() == 1 {
+        return Ok(entries[0].0.to_lowercase());
+    }
+
+    for (digest, file_name) in entries {
+        // Listings may record paths rather than bare names (e.g. "dist/<file name>")
+        let matches = match file_name {
+            Some(file_name) => file_name
+                .trim_start_matches('*')
+                .rsplit(['/', '\\'])
+                .next()
+                .map(|x| x == name)
+                .unwrap_or(false),
+            None => true,
+        };
+
+        if matches {
+            return Ok(digest.to_lowercase());
+        }
+    }
+
+    Err(format!("No checksum was published for {:?}", name))
+}
+
+/// Computes the lowercase hex-encoded SHA-256 digest of a file on disk.
+fn hash_file(path: &Path) -> Result<String, String> {
+    let mut file = std::fs::File::open(path)
+        .map_err(|x| format!("Unable to open downloaded file: {:?}", x))?;
+
+    let mut hasher = Sha256::new();
+    copy(&mut file, &mut hasher).map_err(|x| format!("Unable to read downloaded file: {:?}", x))?;
+
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Verifies a downloaded file against its detached minisign signature.
+fn verify_signature(
+    path: &Path,
+    signing_key: &str,
+    file: &File,
+    token: &dyn Fn(&str) -> Option<String>,
+) -> Result<(), String> {
+    let signature_url = file.signature_url.as_ref().ok_or_else(|| {
+        format!(
+            "No signature was published for {:?}, refusing to install it.",
+            file.name
+        )
+    })?;
+
+    let public_key = PublicKey::from_base64(signing_key)
+        .map_err(|x| format!("Configured signing key is invalid: {:?}", x))?;
+
+    let mut data = Vec::new();
+    stream_file(signature_url, token(signature_url), |chunk, _| {
+        data.extend_from_slice(&chunk);
+        Ok(())
+    })?;
+
+    let signature = String::from_utf8(data)
+        .map_err(|x| format!("Signature for {:?} is invalid: {:?}", file.name, x))
+        .and_then(|x| {
+            Signature::decode(&x)
+                .map_err(|x| format!("Signature for {:?} is invalid: {:?}", file.name, x))
+        })?;
+
+    let mut verifier = public_key
+        .verify_stream(&signature)
+        .map_err(|x| format!("Signature for {:?} is unsupported: {:?}", file.name, x))?;
+
+    let mut data = std::fs::File::open(path)
+        .map_err(|x| format!("Unable to open downloaded file: {:?}", x))?;
+
+    let mut buf = [0u8; 8192];
+    loop {
+        let len = data
+            .read(&mut buf)
+            .map_err(|x| format!("Unable to read downloaded file: {:?}", x))?;
+
+        if len == 0 {
+            break;
+        }
+
+        verifier.update(&buf[0..len]);
+    }
+
+    verifier.finalize().map_err(|x| {
+        format!(
+            "Signature verification failed for {:?} ({:?}). \
+             The download may have been tampered with.",
+            file.name, x
+        )
+    })
+}
@@ -17,8 +17,12 @@ use crate::tasks::TaskMessage;
 use crate::tasks::TaskOrdering;
 use crate::tasks::TaskParamType;
 
+use std::collections::HashMap;
+
 pub struct InstallTask {
     pub items: Vec<String>,
+    /// Specific versions requested for packages, by name
+    pub versions: HashMap<String, String>,
     pub uninstall_items: Vec<String>,
     pub fresh_install: bool,
     pub create_desktop_shortcuts: bool,
@@ -72,10 +76,11 @@ impl Task for InstallTask {
         for item in &self.items {
             elements.push(TaskDependency::build(
                 TaskOrdering::Pre,
-                Box::new(InstallPackageTask {
-                    name: item.clone(),
-                    create_desktop_shortcuts: self.create_desktop_shortcuts,
-                }),
+                Box::new(InstallPackageTask::new(
+                    item.clone(),
+                    self.create_desktop_shortcuts,
+                    self.versions.get(item).cloned(),
+                )),
             ));
         }
 
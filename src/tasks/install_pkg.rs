@@ -1,11 +1,14 @@
 //! Installs a specific package.
+//!
+//! Packages are extracted into a staging directory first, and only swapped into the
+//! installation directory once extraction has fully succeeded. The previous version is kept
+//! aside until the install is committed, so that it can be restored on failure.
 
 use crate::installer::InstallerFramework;
 
 use crate::tasks::download_pkg::DownloadPackageTask;
 use crate::tasks::install_shortcuts::InstallShortcutsTask;
 use crate::tasks::save_database::SaveDatabaseTask;
-use crate::tasks::uninstall_pkg::UninstallPackageTask;
 use crate::tasks::Task;
 use crate::tasks::TaskDependency;
 use crate::tasks::TaskMessage;
@@ -13,23 +16,54 @@ use crate::tasks::TaskOrdering;
 use crate::tasks::TaskParamType;
 
 use crate::config::PackageDescription;
+use crate::installer::InstallationDatabase;
 use crate::installer::LocalInstallation;
+use crate::installer::RetainedVersion;
 
+use crate::tasks::rollback_pkg::version_dir;
+
+use std::fs::copy as copy_file;
 use std::fs::create_dir_all;
+use std::fs::hard_link;
+use std::fs::read_dir;
+use std::fs::read_to_string;
+use std::fs::remove_dir;
+use std::fs::remove_dir_all;
+use std::fs::remove_file;
+use std::fs::rename;
+use std::fs::write;
+use std::fs::File;
 use std::io::copy;
 
 use crate::logging::LoggingErrors;
 
 use crate::archives;
+use crate::archives::EntryKind;
 
 use crate::tasks::install_desktop_shortcut::InstallDesktopShortcutTask;
 use std::collections::HashSet;
 use std::fs::OpenOptions;
 use std::path::Path;
+use std::path::PathBuf;
 
 pub struct InstallPackageTask {
     pub name: String,
     pub create_desktop_shortcuts: bool,
+    /// A specific version to install and pin the package to, or "latest" to unpin it.
+    pub version: Option<String>,
+    transaction: Option<InstallTransaction>,
+}
+
+impl InstallPackageTask {
+    /// Creates a new task to install (or update) the named package.
+    pub fn new(name: String, create_desktop_shortcuts: bool, version: Option<String>) -> Self {
+        InstallPackageTask {
+            name,
+            create_desktop_shortcuts,
+            version,
+            transaction: None,
+        }
+    }
 }
 
 impl Task for InstallPackageTask {
@@ -46,7 +80,7 @@ impl Task for InstallPackageTask {
 
         let path = context
             .install_path
-            .as_ref()
+            .clone()
             .log_expect("No install path specified");
 
         let mut installed_files = Vec::new();
@@ -69,28 +103,100 @@ impl Task for InstallPackageTask {
             None => return Err(format!("Package {:?} could not be found.", self.name)),
         };
 
-        // Ignore input from the uninstaller - no useful information passed
-        // If a previous task Breaks, then just early exit
-        match input
-            .pop()
-            .log_expect("Install Package Task should have guaranteed output!")
-        {
-            TaskParamType::Break => return Ok(TaskParamType::None),
-            _ => (),
-        };
-
-        // Grab data from the resolver
+        // Grab data from the downloader. If it Breaks, then just early exit
         let data = input
             .pop()
             .log_expect("Install Package Task should have input from resolver!");
-        let (version, file, data) = match data {
-            TaskParamType::FileContents(version, file, data) => (version, file, data),
+        let (version, file, source, download_path) = match data {
+            TaskParamType::FileContents(version, file, source, download_path) => {
+                (version, file, source, download_path)
+            }
+            TaskParamType::Break => return Ok(TaskParamType::None),
             _ => return Err("Unexpected file contents param type to install package".to_string()),
         };
 
-        let mut archive = archives::read_archive(&file.name, data.as_slice())?;
+        let staging_dir = path.join(format!(".{}.staging", self.name));
+        let backup_dir = path.join(format!(".{}.backup", self.name));
+
+        // Clear out anything left behind by an interrupted install
+        if staging_dir.exists() {
+            warn!("Removing leftover directory: {:?}", staging_dir);
+            remove_dir_all(&staging_dir)
+                .map_err(|x| format!("Unable to remove leftover directory: {:?}", x))?;
+        }
+
+        if backup_dir.exists() {
+            let installed = context
+                .database
+                .packages
+                .iter()
+                .find(|x| x.name == self.name);
+
+            match read_to_string(backup_marker(&backup_dir)).ok() {
+                // The database was never saved, so still lists the files moved aside
+                Some(version)
+                    if Some(version.clone()) == installed.map(|x| x.version.to_string()) =>
+                {
+                    warn!(
+                        "Restoring {:?} {} from an interrupted install",
+                        self.name, version
+                    );
+                    restore_backup(&backup_dir, &path)?;
+                }
+                // The install was saved, and only cleaning up was interrupted
+                Some(version) => {
+                    let retained = installed.and_then(|x| {
+                        x.retained_versions
+                            .iter()
+                            .find(|x| x.version.to_string() == version)
+                    });
+
+                    match retained {
+                        Some(retained) => {
+                            let target = path.join(&retained.path);
+                            warn!(
+                                "Keeping {:?} {} from an interrupted install in {:?}",
+                                self.name, version, target
+                            );
+                            move_backup(&backup_dir, &target)
+                                .map_err(|x| format!("Unable to keep previous version: {:?}", x))?;
+                        }
+                        None => {
+                            warn!("Removing leftover directory: {:?}", backup_dir);
+                            remove_dir_all(&backup_dir).map_err(|x| {
+                                format!("Unable to remove leftover directory: {:?}", x)
+                            })?;
+                        }
+                    }
+                }
+                None => {
+                    return Err(format!(
+                        "Files of {:?} from an interrupted install were left in {:?}. Move \
+                         them back into place (or remove them), then try again.",
+                        self.name, backup_dir
+                    ));
+                }
+            }
+
+            remove_backup_marker(&backup_dir);
+        }
 
-        archive.for_each(&mut |i, archive_size, filename, mut file| {
+        create_dir_all(&staging_dir)
+            .map_err(|x| format!("Unable to create staging directory: {:?}", x))?;
+
+        let data = File::open(&download_path)
+            .map_err(|x| format!("Unable to open downloaded file: {:?}", x))?;
+
+        let mut archive = archives::read_archive(
+            &file.name,
+            data,
+            source.raw_path.as_deref(),
+            source.format.as_deref(),
+            source.entry_filter()?,
+        )?;
+
+        let result = archive.for_each(&mut |i, archive_size, entry, mut file| {
+            let filename = entry.path;
             let string_name = filename
                 .to_str()
                 .ok_or("Unable to get str from file name")?
@@ -125,49 +231,174 @@ impl Task for InstallPackageTask {
                     continue;
                 }
 
+                // Links extracted earlier may point anywhere, so never write through them
+                let is_symlink = staging_dir
+                    .join(parent_dir)
+                    .symlink_metadata()
+                    .map(|x| x.file_type().is_symlink())
+                    .unwrap_or(false);
+
+                if is_symlink {
+                    return Err(format!(
+                        "Archive entry {:?} would be extracted through the link {:?}.",
+                        filename, string_name
+                    ));
+                }
+
                 if !installed_files.contains(&string_name) {
                     info!("Creating dir: {:?}", string_name);
                     installed_files.push(string_name);
                 }
 
-                match create_dir_all(path.join(&parent_dir)) {
+                match create_dir_all(staging_dir.join(&parent_dir)) {
                     Ok(v) => v,
                     Err(v) => return Err(format!("Unable to create dir: {:?}", v)),
                 }
             }
 
-            // Create target file
-            let target_path = path.join(&filename);
+            let target_path = staging_dir.join(&filename);
 
-            info!("Creating file: {:?}", string_name);
+            match entry.kind {
+                EntryKind::File => {
+                    // Create target file
+                    info!("Creating file: {:?}", string_name);
+
+                    let mut file_metadata = OpenOptions::new();
+                    file_metadata.write(true).create_new(true);
+
+                    // The process umask is applied on top of this. Archives that don't
+                    // record modes get executable files, as they always have.
+                    #[cfg(unix)]
+                    {
+                        use std::os::unix::fs::OpenOptionsExt;
+
+                        file_metadata.mode(entry.mode.unwrap_or(0o770));
+                    }
+
+                    let mut target_file = match file_metadata.open(target_path) {
+                        Ok(v) => v,
+                        Err(v) => return Err(format!("Unable to open file handle: {:?}", v)),
+                    };
+
+                    // Cross the streams
+                    match copy(&mut file, &mut target_file) {
+                        Ok(v) => v,
+                        Err(v) => return Err(format!("Unable to write to file: {:?}", v)),
+                    };
+                }
+                EntryKind::Symlink(link) => {
+                    info!("Creating symlink: {:?} -> {:?}", string_name, link);
+
+                    #[cfg(unix)]
+                    {
+                        if let Err(v) = std::os::unix::fs::symlink(&link, &target_path) {
+                            return Err(format!("Unable to create symlink: {:?}", v));
+                        }
+                    }
+
+                    #[cfg(not(unix))]
+                    {
+                        warn!("Skipping symlink {:?}, as it isn't supported", string_name);
+                        return Ok(());
+                    }
+                }
+                EntryKind::HardLink(original) => {
+                    info!("Creating hard link: {:?} -> {:?}", string_name, original);
+
+                    let original = staging_dir.join(original);
+
+                    // Not every filesystem supports hard links, so fall back to a copy
+                    if let Err(v) = hard_link(&original, &target_path) {
+                        warn!("Unable to create hard link ({:?}), copying instead", v);
+
+                        if let Err(v) = copy_file(&original, &target_path) {
+                            return Err(format!("Unable to copy linked file: {:?}", v));
+                        }
+                    }
+                }
+            }
 
             if !installed_files.contains(&string_name) {
                 installed_files.push(string_name);
             }
 
-            let mut file_metadata = OpenOptions::new();
-            file_metadata.write(true).create_new(true);
+            Ok(())
+        });
+
+        // The downloaded file is no longer needed, whether or not extraction succeeded
+        if let Err(v) = remove_file(&download_path) {
+            warn!("Unable to remove downloaded file: {:?}", v);
+        }
+
+        if let Err(v) = result {
+            if let Err(v) = remove_dir_all(&staging_dir) {
+                warn!("Unable to remove staging directory: {:?}", v);
+            }
+
+            return Err(v);
+        }
 
-            #[cfg(unix)]
-            {
-                use std::os::unix::fs::OpenOptionsExt;
+        messenger(&TaskMessage::DisplayMessage(
+            &format!("Replacing previous version of {:?}...", self.name),
+            1.0,
+        ));
 
-                file_metadata.mode(0o770);
+        // Swap the staged files in place of the previous version
+        let mut previous = None;
+        for i in 0..context.database.packages.len() {
+            if self.name == context.database.packages[i].name {
+                previous = Some(context.database.packages.remove(i));
+                break;
             }
+        }
 
-            let mut target_file = match file_metadata.open(target_path) {
-                Ok(v) => v,
-                Err(v) => return Err(format!("Unable to open file handle: {:?}", v)),
-            };
+        // Work out which versions will be kept around for rolling back to
+        let mut retained_versions = Vec::new();
+        let mut discarded = Vec::new();
+        if let Some(previous) = &previous {
+            if package.keep_versions > 0 {
+                retained_versions.push(RetainedVersion {
+                    version: previous.version.clone(),
+                    path: version_dir(&self.name, &previous.version),
+                    files: previous.files.clone(),
+                });
+            }
 
-            // Cross the streams
-            match copy(&mut file, &mut target_file) {
-                Ok(v) => v,
-                Err(v) => return Err(format!("Unable to write to file: {:?}", v)),
-            };
+            for retained in &previous.retained_versions {
+                if retained.version == version {
+                    discarded.push(retained.clone());
+                } else {
+                    retained_versions.push(retained.clone());
+                }
+            }
+        }
 
-            Ok(())
-        })?;
+        if retained_versions.len() > package.keep_versions {
+            discarded.extend(retained_versions.split_off(package.keep_versions));
+        }
+
+        let mut transaction = InstallTransaction {
+            backup_dir,
+            retained: retained_versions
+                .first()
+                .filter(|_| package.keep_versions > 0)
+                .cloned(),
+            discarded,
+            previous,
+            backed_up: Vec::new(),
+            installed: Vec::new(),
+        };
+
+        let result = transaction.swap_in(&path, &staging_dir, &installed_files);
+
+        if let Err(v) = remove_dir_all(&staging_dir) {
+            warn!("Unable to remove staging directory: {:?}", v);
+        }
+
+        if let Err(v) = result {
+            transaction.revert(&path, &mut context.database, &self.name);
+            return Err(v);
+        }
 
         // Save metadata about this package
         context.database.packages.push(LocalInstallation {
@@ -175,8 +406,16 @@ impl Task for InstallPackageTask {
             version,
             shortcuts: HashSet::new(),
             files: installed_files,
+            retained_versions,
+            pinned: self
+                .version
+                .as_ref()
+                .map(|x| x != "latest")
+                .unwrap_or(false),
         });
 
+        self.transaction = Some(transaction);
+
         messenger(&TaskMessage::PackageInstalled);
 
         Ok(TaskParamType::None)
@@ -188,13 +427,7 @@ impl Task for InstallPackageTask {
                 TaskOrdering::Pre,
                 Box::new(DownloadPackageTask {
                     name: self.name.clone(),
-                }),
-            ),
-            TaskDependency::build(
-                TaskOrdering::Pre,
-                Box::new(UninstallPackageTask {
-                    name: self.name.clone(),
-                    optional: true,
+                    version: self.version.clone(),
                 }),
             ),
             TaskDependency::build(
@@ -217,4 +450,307 @@ impl Task for InstallPackageTask {
     fn name(&self) -> String {
         format!("InstallPackageTask (for {:?})", self.name)
     }
+
+    fn rollback(&mut self, context: &mut InstallerFramework) {
+        let path = context
+            .install_path
+            .clone()
+            .log_expect("No install path specified");
+
+        if let Some(transaction) = self.transaction.take() {
+            transaction.revert(&path, &mut context.database, &self.name);
+        }
+    }
+
+    fn commit(&mut self, context: &mut InstallerFramework) {
+        let path = context
+            .install_path
+            .clone()
+            .log_expect("No install path specified");
+
+        if let Some(transaction) = self.transaction.take() {
+            transaction.commit(&path, &context.database, &self.name);
+        }
+    }
+}
+
+/// Changes made to the installation directory by a package install which have not yet
+/// been committed.
+struct InstallTransaction {
+    /// Holds the files of the previous version until the install is committed.
+    backup_dir: PathBuf,
+    /// Where to keep the files of the previous version once committed, if at all.
+    retained: Option<RetainedVersion>,
+    /// Older retained versions to delete once committed.
+    discarded: Vec<RetainedVersion>,
+    /// The database entry of the previous version, if any.
+    previous: Option<LocalInstallation>,
+    /// Relative paths of files moved from the installation into the backup directory.
+    backed_up: Vec<String>,
+    /// Relative paths of files and directories moved into the installation directory.
+    installed: Vec<String>,
+}
+
+impl InstallTransaction {
+    /// Moves the previous version's files aside, and the staged files into place.
+    fn swap_in(&mut self, path: &Path, staging_dir: &Path, files: &[String]) -> Result<(), String> {
+        if let Some(previous) = &self.previous {
+            // Records which version is backed up, in case the install is interrupted
+            write(
+                backup_marker(&self.backup_dir),
+                previous.version.to_string(),
+            )
+            .map_err(|x| format!("Unable to create backup marker: {:?}", x))?;
+
+            for file in &previous.files {
+                let source = path.join(file);
+
+                // Directories are cleaned up once the install is committed
+                if is_real_dir(&source) || !path_exists(&source) {
+                    continue;
+                }
+
+                let target = self.backup_dir.join(file);
+                if let Some(parent) = target.parent() {
+                    create_dir_all(parent)
+                        .map_err(|x| format!("Unable to create backup directory: {:?}", x))?;
+                }
+
+                rename(&source, &target)
+                    .map_err(|x| format!("Unable to back up {:?}: {:?}", file, x))?;
+                self.backed_up.push(file.clone());
+            }
+        }
+
+        for file in files {
+            let source = staging_dir.join(file);
+            let target = path.join(file);
+
+            if is_real_dir(&source) {
+                create_dir_all(&target).map_err(|x| format!("Unable to create dir: {:?}", x))?;
+            } else {
+                if path_exists(&target) {
+                    return Err(format!(
+                        "Unable to install {:?}, as it already exists.",
+                        file
+                    ));
+                }
+
+                rename(&source, &target)
+                    .map_err(|x| format!("Unable to move {:?} into place: {:?}", file, x))?;
+            }
+
+            self.installed.push(file.clone());
+        }
+
+        Ok(())
+    }
+
+    /// Removes the new files and restores the previous version, along with its database entry.
+    fn revert(self, path: &Path, database: &mut InstallationDatabase, name: &str) {
+        let mut new_shortcuts = HashSet::new();
+        for i in 0..database.packages.len() {
+            if name == database.packages[i].name {
+                new_shortcuts = database.packages.remove(i).shortcuts;
+                break;
+            }
+        }
+
+        let mut directories = Vec::new();
+        for file in &self.installed {
+            let file = path.join(file);
+
+            if is_real_dir(&file) {
+                directories.push(file);
+            } else if let Err(v) = remove_file(&file) {
+                error!("Failed to delete file {:?}: {:?}", file, v);
+            }
+        }
+
+        remove_directories(directories);
+
+        for file in &self.backed_up {
+            let target = path.join(file);
+            if let Some(parent) = target.parent() {
+                if let Err(v) = create_dir_all(parent) {
+                    error!("Failed to create dir {:?}: {:?}", parent, v);
+                }
+            }
+
+            if let Err(v) = rename(self.backup_dir.join(file), &target) {
+                error!("Failed to restore {:?}: {:?}", target, v);
+            }
+        }
+
+        if self.backup_dir.exists() {
+            if let Err(v) = remove_dir_all(&self.backup_dir) {
+                warn!("Unable to remove backup directory: {:?}", v);
+            }
+        }
+
+        remove_backup_marker(&self.backup_dir);
+
+        let old_shortcuts = match &self.previous {
+            Some(previous) => previous.shortcuts.clone(),
+            None => HashSet::new(),
+        };
+
+        for shortcut in new_shortcuts.difference(&old_shortcuts) {
+            if let Err(v) = remove_file(shortcut) {
+                error!("Failed to delete shortcut {:?}: {:?}", shortcut, v);
+            }
+        }
+
+        if let Some(previous) = self.previous {
+            info!("Restored previous version of {:?}", name);
+            database.packages.push(previous);
+        }
+    }
+
+    /// Discards the previous version of the package, or retains it for rolling back to.
+    fn commit(self, path: &Path, database: &InstallationDatabase, name: &str) {
+        if let Some(retained) = &self.retained {
+            let target = path.join(&retained.path);
+            info!("Keeping {:?} {} in {:?}", name, retained.version, target);
+
+            let result = create_dir_all(&self.backup_dir)
+                .and_then(|_| move_backup(&self.backup_dir, &target));
+
+            if let Err(v) = result {
+                error!("Failed to keep previous version: {:?}", v);
+            }
+        }
+
+        if self.backup_dir.exists() {
+            if let Err(v) = remove_dir_all(&self.backup_dir) {
+                warn!("Unable to remove backup directory: {:?}", v);
+            }
+        }
+
+        remove_backup_marker(&self.backup_dir);
+
+        for discarded in &self.discarded {
+            info!("Removing {:?} {}", name, discarded.version);
+
+            let target = path.join(&discarded.path);
+            if target.exists() {
+                if let Err(v) = remove_dir_all(&target) {
+                    warn!("Unable to remove previous version: {:?}", v);
+                }
+            }
+        }
+
+        let previous = match self.previous {
+            Some(v) => v,
+            None => return,
+        };
+
+        let current = database.packages.iter().find(|x| x.name == name);
+
+        // Remove shortcuts that weren't regenerated for the new version
+        for shortcut in &previous.shortcuts {
+            let regenerated = current
+                .map(|x| x.shortcuts.contains(shortcut))
+                .unwrap_or(false);
+
+            if !regenerated {
+                if let Err(v) = remove_file(shortcut) {
+                    error!("Failed to delete shortcut {:?}: {:?}", shortcut, v);
+                }
+            }
+        }
+
+        // Any directories now left empty belonged only to the previous version
+        let directories = previous
+            .files
+            .iter()
+            .map(|x| path.join(x))
+            .filter(|x| is_real_dir(x))
+            .collect();
+
+        remove_directories(directories);
+    }
+}
+
+/// Returns the path of the file recording which version of a package a backup directory
+/// holds the files of.
+fn backup_marker(backup_dir: &Path) -> PathBuf {
+    let mut marker = backup_dir.as_os_str().to_owned();
+    marker.push(".version");
+    PathBuf::from(marker)
+}
+
+/// Removes the marker of a backup directory, if there is one.
+fn remove_backup_marker(backup_dir: &Path) {
+    let marker = backup_marker(backup_dir);
+    if path_exists(&marker) {
+        if let Err(v) = remove_file(&marker) {
+            warn!("Unable to remove backup marker: {:?}", v);
+        }
+    }
+}
+
+/// Moves a backup directory to where a retained version is kept, replacing anything
+/// already there.
+fn move_backup(backup_dir: &Path, target: &Path) -> std::io::Result<()> {
+    if let Some(parent) = target.parent() {
+        create_dir_all(parent)?;
+    }
+
+    if target.exists() {
+        remove_dir_all(target)?;
+    }
+
+    rename(backup_dir, target)
+}
+
+/// Moves the files of a backup left by an interrupted install back into the installation
+/// directory, replacing anything that the new version put in their place.
+fn restore_backup(backup_dir: &Path, path: &Path) -> Result<(), String> {
+    let entries =
+        read_dir(backup_dir).map_err(|x| format!("Unable to read {:?}: {:?}", backup_dir, x))?;
+
+    for entry in entries {
+        let entry = entry.map_err(|x| format!("Unable to read {:?}: {:?}", backup_dir, x))?;
+        let source = entry.path();
+        let target = path.join(entry.file_name());
+
+        if is_real_dir(&source) {
+            create_dir_all(&target).map_err(|x| format!("Unable to create dir: {:?}", x))?;
+            restore_backup(&source, &target)?;
+            continue;
+        }
+
+        if path_exists(&target) {
+            remove_file(&target).map_err(|x| format!("Unable to replace {:?}: {:?}", target, x))?;
+        }
+
+        rename(&source, &target).map_err(|x| format!("Unable to restore {:?}: {:?}", target, x))?;
+    }
+
+    remove_dir(backup_dir).map_err(|x| format!("Unable to remove {:?}: {:?}", backup_dir, x))
+}
+
+/// Returns whether a path is a directory. Unlike `Path::is_dir`, symlinks aren't followed,
+/// so that links to directories are handled like files.
+pub fn is_real_dir(path: &Path) -> bool {
+    path.symlink_metadata().map(|x| x.is_dir()).unwrap_or(false)
+}
+
+/// Returns whether anything, including a dangling symlink, exists at a path.
+pub fn path_exists(path: &Path) -> bool {
+    path.symlink_metadata().is_ok()
+}
+
+/// Removes the specified directories if they are empty, deepest first.
+pub fn remove_directories(mut directories: Vec<PathBuf>) {
+    directories.sort_by(|a, b| {
+        let depth_a = a.components().fold(0usize, |acc, _| acc + 1);
+        let depth_b = b.components().fold(0usize, |acc, _| acc + 1);
+        depth_b.cmp(&depth_a)
+    });
+
+    for i in directories.iter() {
+        remove_dir(i).ok();
+    }
 }
@@ -3,9 +3,12 @@
 
 use std::fmt;
 use std::fmt::Display;
+use std::path::PathBuf;
 
 use crate::installer::InstallerFramework;
 
+use crate::config::PackageSource;
+
 use crate::sources::types::File;
 use crate::sources::types::Version;
 
@@ -21,6 +24,7 @@ pub mod install_shortcuts;
 pub mod launch_installed_on_exit;
 pub mod remove_target_dir;
 pub mod resolver;
+pub mod rollback_pkg;
 pub mod save_database;
 pub mod save_executable;
 pub mod uninstall;
@@ -31,12 +35,12 @@ pub mod uninstall_shortcuts;
 /// An abstraction over the various parameters that can be passed around.
 pub enum TaskParamType {
     None,
-    /// Metadata about a file
-    File(Version, File),
+    /// Metadata about a file, and the source that it is served by
+    File(Version, File, PackageSource),
     /// Authentication token for a package
-    Authentication(Version, File, Option<String>),
-    /// Downloaded contents of a file
-    FileContents(Version, File, Vec<u8>),
+    Authentication(Version, File, PackageSource, Option<String>),
+    /// Path to the downloaded contents of a file
+    FileContents(Version, File, PackageSource, PathBuf),
     /// List of shortcuts that have been generated
     GeneratedShortcuts(Vec<String>),
     /// Tells the runtime to break parsing other dependencies
@@ -90,12 +94,20 @@ pub trait Task {
 
     /// Returns a short name used for formatting the dependency tree.
     fn name(&self) -> String;
+
+    /// Reverts any changes made by this task after something else in the tree failed.
+    /// This is only called if this task itself completed successfully.
+    fn rollback(&mut self, _context: &mut InstallerFramework) {}
+
+    /// Finalises any changes made by this task once the entire tree has succeeded.
+    fn commit(&mut self, _context: &mut InstallerFramework) {}
 }
 
 /// The dependency tree allows for smart iteration on a Task struct.
 pub struct DependencyTree {
     task: Box<dyn Task>,
     dependencies: Vec<(TaskOrdering, DependencyTree)>,
+    completed: bool,
 }
 
 impl DependencyTree {
@@ -176,6 +188,8 @@ impl DependencyTree {
                 _ => messenger(msg),
             })?;
 
+        self.completed = true;
+
         if let TaskParamType::Break = task_result {
             // We are done here
             return Ok(TaskParamType::Break);
@@ -212,6 +226,47 @@ impl DependencyTree {
         Ok(task_result)
     }
 
+    /// Reverts every completed task in this pipeline, in the reverse order of execution.
+    pub fn rollback(&mut self, context: &mut InstallerFramework) {
+        for (ordering, i) in self.dependencies.iter_mut().rev() {
+            if ordering == &TaskOrdering::Post {
+                i.rollback(context);
+            }
+        }
+
+        if self.completed {
+            info!("Rolling back {}", self.task.name());
+            self.task.rollback(context);
+            self.completed = false;
+        }
+
+        for (ordering, i) in self.dependencies.iter_mut().rev() {
+            if ordering == &TaskOrdering::Pre {
+                i.rollback(context);
+            }
+        }
+    }
+
+    /// Commits every completed task in this pipeline, in the order of execution.
+    pub fn commit(&mut self, context: &mut InstallerFramework) {
+        for (ordering, i) in &mut self.dependencies {
+            if ordering == &TaskOrdering::Pre {
+                i.commit(context);
+            }
+        }
+
+        if self.completed {
+            self.task.commit(context);
+            self.completed = false;
+        }
+
+        for (ordering, i) in &mut self.dependencies {
+            if ordering == &TaskOrdering::Post {
+                i.commit(context);
+            }
+        }
+    }
+
     /// Builds a new pipeline from the specified task, iterating on dependencies.
     pub fn build(task: Box<dyn Task>) -> DependencyTree {
         let dependencies = task
@@ -220,7 +275,11 @@ impl DependencyTree {
             .map(|x| (x.ordering, DependencyTree::build(x.task)))
             .collect();
 
-        DependencyTree { task, dependencies }
+        DependencyTree {
+            task,
+            dependencies,
+            completed: false,
+        }
     }
 }
 
@@ -1,7 +1,5 @@
 //! Resolves package names into a metadata + version object.
 
-use std::env::consts::OS;
-
 use crate::installer::InstallerFramework;
 
 use crate::tasks::Task;
@@ -11,12 +9,13 @@ use crate::tasks::TaskParamType;
 
 use crate::config::PackageDescription;
 
-use regex::Regex;
-
 use crate::logging::LoggingErrors;
 
 pub struct ResolvePackageTask {
     pub name: String,
+    /// A specific version to install, or "latest". If not specified, pinned packages are
+    /// left alone.
+    pub version: Option<String>,
 }
 
 impl Task for ResolvePackageTask {
@@ -45,6 +44,21 @@ impl Task for ResolvePackageTask {
             None => return Err(format!("Package {:?} could not be found.", self.name)),
         };
 
+        // Pinned packages are only changed if explicitly requested
+        if self.version.is_none() {
+            for element in &context.database.packages {
+                if element.name == self.name && element.pinned {
+                    info!(
+                        "{:?} is pinned to version {}, skipping update.",
+                        self.name, element.version
+                    );
+                    return Ok(TaskParamType::Break);
+                }
+            }
+        }
+
+        let target_version = self.version.as_ref().filter(|x| *x != "latest");
+
         messenger(&TaskMessage::DisplayMessage(
             &format!(
                 "Polling {} for latest version of {:?}...",
@@ -53,42 +67,72 @@ impl Task for ResolvePackageTask {
             0.0,
         ));
 
-        let results = package.source.get_current_releases()?;
+        let (source, results) = package.get_current_releases(&context.fetch_options())?;
 
         messenger(&TaskMessage::DisplayMessage(
             &format!("Resolving dependency for {:?}...", package.name),
             0.5,
         ));
 
-        let filtered_regex = package.source.match_regex.replace("#PLATFORM#", OS);
-        let regex = match Regex::new(&filtered_regex) {
-            Ok(v) => v,
-            Err(v) => return Err(format!("An error occurred while compiling regex: {:?}", v)),
-        };
+        let regex = source.compile_match_regex()?;
 
-        // Find the latest release in here
-        let latest_result = results
+        // Find the latest (or requested) release in here
+        let candidates = results
             .into_iter()
-            .filter(|f| f.files.iter().filter(|x| regex.is_match(&x.name)).count() > 0)
-            .max_by_key(|f| f.version.clone());
+            .filter(|f| f.files.iter().filter(|x| regex.is_match(&x.name)).count() > 0);
 
-        let latest_result = match latest_result {
-            Some(v) => v,
-            None => return Err("No release with correct file found".to_string()),
+        let latest_result = match target_version {
+            Some(version) => candidates
+                .filter(|f| &f.version.to_string() == version)
+                .max_by_key(|f| f.version.clone()),
+            None => candidates.max_by_key(|f| f.version.clone()),
+        };
+
+        let mut latest_result = match (latest_result, target_version) {
+            (Some(v), _) => v,
+            (None, Some(version)) => {
+                return Err(format!(
+                    "Version {} of {:?} could not be found.",
+                    version, package.name
+                ))
+            }
+            (None, None) => return Err("No release with correct file found".to_string()),
         };
 
         let latest_version = latest_result.version.clone();
 
         // Find the matching file in here
-        let latest_file = latest_result
+        let mut latest_file = latest_result
             .files
-            .into_iter()
-            .find(|x| regex.is_match(&x.name))
+            .iter()
+            .position(|x| regex.is_match(&x.name))
+            .map(|x| latest_result.files.remove(x))
             .log_expect("Searched file should have existed, but didn't");
 
-        info!("Selected file: {:?}", latest_file);
+        // Look for a detached signature published next to it
+        if latest_file.signature_url.is_none() {
+            let signature_names = [
+                format!("{}.minisig", latest_file.name),
+                format!("{}.sig", latest_file.name),
+            ];
+
+            latest_file.signature_url = latest_result
+                .files
+                .iter()
+                .find(|x| signature_names.contains(&x.name))
+                .map(|x| x.url.clone());
+        }
+
+        info!(
+            "Selected file from {} source: {:?}",
+            source.name, latest_file
+        );
 
-        Ok(TaskParamType::File(latest_version, latest_file))
+        Ok(TaskParamType::File(
+            latest_version,
+            latest_file,
+            source.clone(),
+        ))
     }
 
     fn dependencies(&self) -> Vec<TaskDependency> {
@@ -0,0 +1,273 @@
+//! Switches a package back to one of its retained previous versions.
+
+use crate::installer::InstallerFramework;
+use crate::installer::RetainedVersion;
+
+use crate::tasks::install_pkg::is_real_dir;
+use crate::tasks::install_pkg::path_exists;
+use crate::tasks::install_pkg::remove_directories;
+use crate::tasks::install_shortcuts::InstallShortcutsTask;
+use crate::tasks::save_database::SaveDatabaseTask;
+use crate::tasks::Task;
+use crate::tasks::TaskDependency;
+use crate::tasks::TaskMessage;
+use crate::tasks::TaskOrdering;
+use crate::tasks::TaskParamType;
+
+use crate::sources::types::Version;
+
+use crate::logging::LoggingErrors;
+
+use std::collections::HashSet;
+use std::fs::create_dir_all;
+use std::fs::remove_dir_all;
+use std::fs::rename;
+use std::path::Path;
+
+/// Returns the relative path of the directory that a retained version of a package is kept in.
+pub fn version_dir(name: &str, version: &Version) -> String {
+    format!(
+        ".versions/{}/{}",
+        name,
+        sanitize_dir_name(&version.to_string())
+    )
+}
+
+/// Makes a version (which may come from anywhere) safe to use as a single directory name.
+/// Separators and other unusual characters are replaced, as are leading dots, so that the
+/// result can't be `..` or otherwise refer outside of its parent.
+fn sanitize_dir_name(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .enumerate()
+        .map(|(i, c)| match c {
+            '.' if i == 0 => '_',
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '.' | '-' | '_' | '+' => c,
+            _ => '_',
+        })
+        .collect();
+
+    if name.is_empty() {
+        "_".to_string()
+    } else {
+        name
+    }
+}
+
+pub struct RollbackPackageTask {
+    pub name: String,
+    /// The version to switch to. If not specified, the most recently retained one is used.
+    pub version: Option<String>,
+    /// If shortcuts should be regenerated, which needs the package's description.
+    pub install_shortcuts: bool,
+}
+
+impl Task for RollbackPackageTask {
+    fn execute(
+        &mut self,
+        input: Vec<TaskParamType>,
+        context: &mut InstallerFramework,
+        messenger: &dyn Fn(&TaskMessage),
+    ) -> Result<TaskParamType, String> {
+        assert_eq!(input.len(), 0);
+
+        let path = context
+            .install_path
+            .clone()
+            .log_expect("No install path specified");
+
+        let package = context
+            .database
+            .packages
+            .iter_mut()
+            .find(|x| x.name == self.name)
+            .ok_or_else(|| format!("Package {:?} is not installed.", self.name))?;
+
+        let index = match &self.version {
+            Some(version) => package
+                .retained_versions
+                .iter()
+                .position(|x| &x.version.to_string() == version),
+            None if package.retained_versions.is_empty() => None,
+            None => Some(0),
+        };
+
+        let index = index.ok_or_else(|| {
+            format!(
+                "No previous version of {:?} is available to roll back to.",
+                self.name
+            )
+        })?;
+
+        let target = package.retained_versions[index].clone();
+
+        messenger(&TaskMessage::DisplayMessage(
+            &format!("Switching {:?} to version {}...", self.name, target.version),
+            0.0,
+        ));
+
+        // Move the active version aside, and the retained one into place
+        let current = RetainedVersion {
+            version: package.version.clone(),
+            path: version_dir(&self.name, &package.version),
+            files: package.files.clone(),
+        };
+
+        let current_dir = path.join(&current.path);
+        let target_dir = path.join(&target.path);
+
+        // Don't move anything out unless there is a complete version to move in
+        if !is_real_dir(&target_dir) {
+            return Err(format!(
+                "The files of {:?} {} are missing from {:?}.",
+                self.name, target.version, target_dir
+            ));
+        }
+
+        let target_files = regular_files(&target.files);
+        if let Some(file) = target_files
+            .iter()
+            .find(|x| !path_exists(&target_dir.join(x)))
+        {
+            return Err(format!(
+                "{:?} {} is incomplete, as {:?} is missing from {:?}.",
+                self.name, target.version, file, target_dir
+            ));
+        }
+
+        // Files that have gone missing from the active version can't be kept
+        let current_files: Vec<String> = regular_files(&current.files)
+            .into_iter()
+            .filter(|x| path_exists(&path.join(x)))
+            .collect();
+
+        if current_dir.exists() {
+            remove_dir_all(&current_dir)
+                .map_err(|x| format!("Unable to remove leftover directory: {:?}", x))?;
+        }
+
+        let mut moved_out = Vec::new();
+        let mut moved_in = Vec::new();
+
+        let result = move_files(&current_files, &path, &current_dir, &mut moved_out)
+            .and_then(|_| move_files(&target_files, &target_dir, &path, &mut moved_in));
+
+        if let Err(v) = result {
+            // Put everything back the way that it was
+            let mut restored = Vec::new();
+            if let Err(v) = move_files(&moved_in, &path, &target_dir, &mut restored) {
+                error!("Failed to restore {:?}: {:?}", target.version, v);
+            }
+            if let Err(v) = move_files(&moved_out, &current_dir, &path, &mut restored) {
+                error!("Failed to restore {:?}: {:?}", current.version, v);
+            }
+
+            return Err(v);
+        }
+
+        if let Err(v) = remove_dir_all(&target_dir) {
+            warn!("Unable to remove retained version directory: {:?}", v);
+        }
+
+        remove_directories(
+            current
+                .files
+                .iter()
+                .map(|x| path.join(x))
+                .filter(|x| is_real_dir(x))
+                .collect(),
+        );
+
+        info!(
+            "Switched {:?} from {} to {}",
+            self.name, current.version, target.version
+        );
+
+        package.retained_versions.remove(index);
+        package.retained_versions.insert(0, current);
+        package.version = target.version;
+        package.files = target.files;
+
+        // Otherwise the next update would just reinstall the version rolled back from.
+        // Installing "latest" unpins the package again.
+        package.pinned = true;
+
+        Ok(TaskParamType::None)
+    }
+
+    fn dependencies(&self) -> Vec<TaskDependency> {
+        let mut dependencies = Vec::new();
+
+        if self.install_shortcuts {
+            dependencies.push(TaskDependency::build(
+                TaskOrdering::Post,
+                Box::new(InstallShortcutsTask {
+                    name: self.name.clone(),
+                }),
+            ));
+        }
+
+        dependencies.push(TaskDependency::build(
+            TaskOrdering::Post,
+            Box::new(SaveDatabaseTask {}),
+        ));
+
+        dependencies
+    }
+
+    fn name(&self) -> String {
+        format!(
+            "RollbackPackageTask (for {:?}, version = {:?})",
+            self.name, self.version
+        )
+    }
+}
+
+/// Returns the entries of a package's file list which aren't directories. Only parents of
+/// other entries are recorded as directories, so they are known even once removed.
+fn regular_files(files: &[String]) -> Vec<String> {
+    let directories: HashSet<&Path> = files
+        .iter()
+        .flat_map(|x| Path::new(x).ancestors().skip(1))
+        .collect();
+
+    files
+        .iter()
+        .filter(|x| !directories.contains(Path::new(x)))
+        .cloned()
+        .collect()
+}
+
+/// Moves the listed files from one root directory to another, recording each file that
+/// was moved. Every file must exist.
+fn move_files(
+    files: &[String],
+    from: &Path,
+    to: &Path,
+    moved: &mut Vec<String>,
+) -> Result<(), String> {
+    for file in files {
+        let source = from.join(file);
+        let target = to.join(file);
+
+        if !path_exists(&source) {
+            return Err(format!("Unable to move {:?}, as it is missing.", source));
+        }
+
+        if path_exists(&target) {
+            return Err(format!(
+                "Unable to move {:?}, as it already exists.",
+                target
+            ));
+        }
+
+        if let Some(parent) = target.parent() {
+            create_dir_all(parent).map_err(|x| format!("Unable to create dir: {:?}", x))?;
+        }
+
+        rename(&source, &target).map_err(|x| format!("Unable to move {:?}: {:?}", file, x))?;
+        moved.push(file.clone());
+    }
+
+    Ok(())
+}
@@ -2,6 +2,7 @@
 
 use crate::installer::InstallerFramework;
 
+use crate::tasks::install_pkg::is_real_dir;
 use crate::tasks::save_database::SaveDatabaseTask;
 use crate::tasks::Task;
 use crate::tasks::TaskDependency;
@@ -12,6 +13,7 @@ use crate::tasks::TaskParamType;
 use crate::installer::LocalInstallation;
 
 use std::fs::remove_dir;
+use std::fs::remove_dir_all;
 use std::fs::remove_file;
 
 use crate::logging::LoggingErrors;
@@ -76,7 +78,7 @@ impl Task for UninstallPackageTask {
                 (i as f64) / (max as f64),
             ));
 
-            let result = if file.is_dir() {
+            let result = if is_real_dir(&file) {
                 // we don't delete directory just yet
                 directories.push(file);
                 Ok(())
@@ -89,6 +91,24 @@ impl Task for UninstallPackageTask {
             }
         }
 
+        // Retained previous versions are kept in their own directories
+        for retained in &package.retained_versions {
+            let retained_dir = path.join(&retained.path);
+            info!("Deleting retained version: {:?}", retained_dir);
+
+            if let Err(v) = remove_dir_all(&retained_dir) {
+                error!("Failed to delete retained version: {:?}", v);
+            }
+
+            if let Some(parent) = retained_dir.parent() {
+                directories.push(parent.to_path_buf());
+
+                if let Some(parent) = parent.parent() {
+                    directories.push(parent.to_path_buf());
+                }
+            }
+        }
+
         // sort directories by reverse depth order
         directories.sort_by(|a, b| {
             let depth_a = a.components().fold(0usize, |acc, _| acc + 1);